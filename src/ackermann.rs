@@ -0,0 +1,212 @@
+use crate::vm::{HookContext, HookResult, Word, MOD};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The teleporter's confirmation routine: Ackermann's function where `A(0, n)`
+/// is `n + 1`, `A(m, 0)` is `A(m - 1, k)` for the eighth register's value `k`,
+/// and everything is taken modulo `modulus`, which is from 1 to 32768.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Confirmation {
+    pub m: Word,
+    pub n: Word,
+    pub modulus: usize,
+    /// What the teleporter expects the routine to return.
+    pub expected: Word,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution {
+    /// Every value of the eighth register that passes, in order.
+    pub values: Vec<Word>,
+    pub elapsed: Duration,
+    pub threads: usize,
+}
+
+/// A `Confirmation` whose modulus is 0 or above 32768.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidModulus(pub usize);
+
+impl Confirmation {
+    pub fn evaluate(&self, k: Word) -> Result<Word, InvalidModulus> {
+        self.check()?;
+        let mut rows = (vec![0; self.modulus], vec![0; self.modulus]);
+        Ok(self.evaluate_with(k as usize, &mut rows))
+    }
+
+    fn check(&self) -> Result<(), InvalidModulus> {
+        if (1..=MOD as usize).contains(&self.modulus) {
+            Ok(())
+        } else {
+            Err(InvalidModulus(self.modulus))
+        }
+    }
+
+    /// Builds the table one row of `m` at a time, so nothing recurses. Only
+    /// the last row stops at `n`; the others are needed in full because any
+    /// value can be looked up in them.
+    fn evaluate_with(&self, k: usize, rows: &mut (Vec<Word>, Vec<Word>)) -> Word {
+        let (previous, current) = rows;
+        let n = self.n as usize % self.modulus;
+
+        for (value, cell) in previous.iter_mut().enumerate() {
+            *cell = ((value + 1) % self.modulus) as Word;
+        }
+
+        for row in 1..=self.m {
+            let length = if row == self.m { n + 1 } else { self.modulus };
+            current[0] = previous[k % self.modulus];
+            for value in 1..length {
+                current[value] = previous[current[value - 1] as usize];
+            }
+            std::mem::swap(previous, current);
+        }
+
+        previous[n]
+    }
+
+    /// Tries every value of the eighth register, using all available cores.
+    pub fn solve(&self) -> Result<Solution, InvalidModulus> {
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        self.solve_with_threads(threads)
+    }
+
+    pub fn solve_with_threads(&self, threads: usize) -> Result<Solution, InvalidModulus> {
+        self.check()?;
+        let start = Instant::now();
+        let threads = threads.clamp(1, self.modulus);
+
+        let mut values: Vec<Word> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|first| {
+                    scope.spawn(move || {
+                        let mut rows = (vec![0; self.modulus], vec![0; self.modulus]);
+                        (first..self.modulus)
+                            .step_by(threads)
+                            .filter(|&k| self.evaluate_with(k, &mut rows) == self.expected)
+                            .map(|k| k as Word)
+                            .collect::<Vec<Word>>()
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        values.sort_unstable();
+
+        Ok(Solution {
+            values,
+            elapsed: start.elapsed(),
+            threads,
+        })
+    }
+}
+
+impl fmt::Display for InvalidModulus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the modulus must be from 1 to {}, not {}", MOD, self.0)
+    }
+}
+
+impl std::error::Error for InvalidModulus {}
+
+/// Does the confirmation routine's work natively, with `m` and `n` taken from
+/// the first two registers and `k` from the eighth, leaving the result in the
+/// first register. Install it with `VM::set_hook`.
+pub fn native_confirmation(hooked: &mut HookContext) -> HookResult {
+    let registers = hooked.get_registers();
+    let confirmation = Confirmation {
+        m: registers[0],
+        n: registers[1],
+        modulus: MOD as usize,
+        expected: 0,
+    };
+    match confirmation.evaluate(registers[7]) {
+        Ok(result) => {
+            hooked.set_register(0, result);
+            HookResult::Returned
+        }
+        Err(_) => HookResult::Declined,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recursive(m: usize, n: usize, k: usize, modulus: usize) -> usize {
+        match (m, n) {
+            (0, _) => (n + 1) % modulus,
+            (_, 0) => recursive(m - 1, k, k, modulus),
+            _ => recursive(m - 1, recursive(m, n - 1, k, modulus), k, modulus),
+        }
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let confirmation = Confirmation {
+            m: 4,
+            n: 1,
+            modulus: 32_768,
+            expected: 6,
+        };
+        assert_eq!(confirmation.evaluate(25_734), Ok(6));
+
+        for (m, n) in [(0, 5), (1, 3), (2, 2), (3, 1)] {
+            let small = Confirmation {
+                m,
+                n,
+                modulus: 32,
+                expected: 0,
+            };
+            for k in 0..32 {
+                assert_eq!(
+                    small.evaluate(k).unwrap() as usize,
+                    recursive(m as usize, n as usize, k as usize, 32),
+                    "A({}, {}) with k = {}",
+                    m,
+                    n,
+                    k
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn solve_test() {
+        let confirmation = Confirmation {
+            m: 3,
+            n: 2,
+            modulus: 64,
+            expected: 5,
+        };
+        let expected: Vec<Word> = (0..64)
+            .filter(|&k| recursive(3, 2, k, 64) == 5)
+            .map(|k| k as Word)
+            .collect();
+
+        assert!(!expected.is_empty());
+        assert_eq!(confirmation.solve_with_threads(3).unwrap().values, expected);
+        assert_eq!(confirmation.solve_with_threads(1).unwrap().values, expected);
+    }
+
+    #[test]
+    fn modulus_test() {
+        for modulus in [0, 32_769] {
+            let confirmation = Confirmation {
+                m: 1,
+                n: 1,
+                modulus,
+                expected: 0,
+            };
+            assert_eq!(confirmation.evaluate(1), Err(InvalidModulus(modulus)));
+            assert_eq!(
+                confirmation.solve_with_threads(2),
+                Err(InvalidModulus(modulus))
+            );
+        }
+    }
+}
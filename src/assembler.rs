@@ -0,0 +1,541 @@
+use crate::vm::{Word, MNEMONICS, MOD, REGISTERS};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assembly {
+    pub words: Vec<Word>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Which disassembler's listings `assemble_with` reads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    /// This crate's disassembler, which quotes characters.
+    Listing,
+    /// The original linear disassembler, which printed `out` characters raw,
+    /// as in `out(a)`, `out(;)` or an `out(` whose newline closes on the
+    /// next line, and undecodable words as `ERROR: unknown opcode: <word>`.
+    Original,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Word(Word),
+    Label(String),
+    Text(Vec<Word>),
+}
+
+/// Operand count and whether the first operand is written to, by opcode.
+pub(crate) const SHAPES: [(usize, bool); 22] = [
+    (0, false),
+    (2, true),
+    (1, false),
+    (1, true),
+    (3, true),
+    (3, true),
+    (1, false),
+    (2, false),
+    (2, false),
+    (3, true),
+    (3, true),
+    (3, true),
+    (3, true),
+    (3, true),
+    (2, true),
+    (2, true),
+    (2, false),
+    (1, false),
+    (0, false),
+    (1, false),
+    (1, true),
+    (0, false),
+];
+
+const ALIASES: [(&str, Word); 5] = [("set", 1), ("jt", 7), ("jf", 8), ("mult", 10), ("nop", 21)];
+
+/// How the original linear disassembler printed words it couldn't decode.
+const UNKNOWN_OPCODE: &str = "ERROR: unknown opcode:";
+
+/// Assembles mnemonic source in the disassembler's syntax, e.g.
+/// `loop: add(#0, #0, 1)`, into a memory image starting at address 0.
+///
+/// Besides instructions, a line may hold `label:` definitions, a numeric
+/// `123:` prefix asserting the current address, `data` directives listing
+/// numbers, characters, strings and labels, and `;` comments. `out` accepts a
+/// string literal, which becomes one `out` per character. Lines with an
+/// address prefix may write to a literal, as data decoded as code does.
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    assemble_with(source, Syntax::Listing)
+}
+
+/// Like `assemble`, but reading listings in the given `syntax`.
+pub fn assemble_with(source: &str, syntax: Syntax) -> Result<Assembly, AssembleError> {
+    let original = syntax == Syntax::Original;
+    let mut words: Vec<Word> = vec![];
+    let mut labels: BTreeMap<String, usize> = BTreeMap::new();
+    let mut fixups: Vec<(usize, usize, String)> = vec![];
+    let mut lines = source.lines().enumerate().peekable();
+
+    while let Some((index, raw_line)) = lines.next() {
+        let line_number = index + 1;
+        let error = |message: String| AssembleError {
+            line: line_number,
+            message,
+        };
+        let mut line = raw_line.trim();
+        let mut listed = false;
+
+        while let Some((name, rest)) =
+            split_label(line).filter(|_| !(original && line.starts_with(UNKNOWN_OPCODE)))
+        {
+            if let Ok(address) = name.parse::<usize>() {
+                listed = true;
+                if address != words.len() {
+                    return Err(error(format!(
+                        "expected address {} but this is address {}",
+                        address,
+                        words.len()
+                    )));
+                }
+            } else if labels.insert(name.to_owned(), words.len()).is_some() {
+                return Err(error(format!("label `{}` is defined twice", name)));
+            }
+            line = rest.trim_start();
+        }
+
+        // Raw characters go before comments and quotes are looked for.
+        if original {
+            if line == "out(" && lines.peek().is_some_and(|(_, next)| next.starts_with(')')) {
+                lines.next();
+                words.extend([19, b'\n' as Word]);
+                continue;
+            }
+            if let Some(character) = raw_out(line) {
+                words.extend([19, character as Word]);
+                continue;
+            }
+            if let Some(word) = line.strip_prefix(UNKNOWN_OPCODE) {
+                let word = word.trim();
+                words.push(
+                    word.parse()
+                        .map_err(|_| error(format!("`{}` is not a word", word)))?,
+                );
+                continue;
+            }
+        }
+
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mnemonic_end = line
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(line.len());
+        let mnemonic = &line[..mnemonic_end];
+        let arguments = arguments(line[mnemonic_end..].trim()).map_err(error)?;
+
+        if mnemonic == "data" {
+            for argument in &arguments {
+                match parse_operand(argument).map_err(error)? {
+                    Operand::Word(word) => words.push(word),
+                    Operand::Text(text) => words.extend(text),
+                    Operand::Label(label) => {
+                        fixups.push((words.len(), line_number, label));
+                        words.push(0);
+                    }
+                }
+            }
+            continue;
+        }
+
+        let opcode =
+            opcode(mnemonic).ok_or_else(|| error(format!("unknown instruction `{}`", mnemonic)))?;
+        let (count, writes) = SHAPES[opcode as usize];
+
+        if arguments.len() != count {
+            return Err(error(format!(
+                "`{}` takes {} operands but was given {}",
+                mnemonic,
+                count,
+                arguments.len()
+            )));
+        }
+
+        let operands = arguments
+            .iter()
+            .map(|argument| parse_operand(argument))
+            .collect::<Result<Vec<Operand>, String>>()
+            .map_err(error)?;
+
+        // A listing reproduces memory word for word, including data that
+        // decodes as an instruction writing to a literal.
+        if writes && !listed && !matches!(operands[0], Operand::Word(word) if word >= MOD) {
+            return Err(error(format!(
+                "the first operand of `{}` must be a register",
+                mnemonic
+            )));
+        }
+
+        if let Some(Operand::Text(text)) = operands.first() {
+            if opcode != 19 {
+                return Err(error(
+                    "strings are only allowed in `out` and `data`".to_owned(),
+                ));
+            }
+            for &character in text {
+                words.push(19);
+                words.push(character);
+            }
+            continue;
+        }
+
+        words.push(opcode);
+        for operand in operands {
+            match operand {
+                Operand::Word(word) => words.push(word),
+                Operand::Label(label) => {
+                    fixups.push((words.len(), line_number, label));
+                    words.push(0);
+                }
+                Operand::Text(_) => {
+                    return Err(error(
+                        "strings are only allowed in `out` and `data`".to_owned(),
+                    ))
+                }
+            }
+        }
+    }
+
+    for (address, line, label) in fixups {
+        match labels.get(&label) {
+            Some(&target) => words[address] = target as Word,
+            None => {
+                return Err(AssembleError {
+                    line,
+                    message: format!("undefined label `{}`", label),
+                })
+            }
+        }
+    }
+
+    Ok(Assembly { words, labels })
+}
+
+pub(crate) fn opcode(mnemonic: &str) -> Option<Word> {
+    MNEMONICS
+        .iter()
+        .position(|candidate| *candidate == mnemonic)
+        .map(|opcode| opcode as Word)
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == mnemonic)
+                .map(|&(_, opcode)| opcode)
+        })
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(open), c) if c == open => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, ';') => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// The character of an `out(c)` with a single raw character, which may be
+/// one that would otherwise start a comment, quote or operand list.
+fn raw_out(line: &str) -> Option<char> {
+    let mut characters = line.strip_prefix("out(")?.chars();
+    let character = characters.next()?;
+    let rest = characters.as_str().strip_prefix(')')?.trim_start();
+
+    if rest.is_empty() || rest.starts_with(';') {
+        Some(character)
+    } else {
+        None
+    }
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+
+    if end > 0 && line[end..].starts_with(':') {
+        Some((&line[..end], &line[end + 1..]))
+    } else {
+        None
+    }
+}
+
+/// Splits `(a, b, c)` or `a, b, c` into operands, keeping quoted commas.
+fn arguments(text: &str) -> Result<Vec<String>, String> {
+    let inner = if let Some(inner) = text.strip_prefix('(') {
+        inner
+            .strip_suffix(')')
+            .ok_or_else(|| "missing closing `)`".to_owned())?
+    } else {
+        text
+    };
+
+    let mut arguments: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for c in inner.chars() {
+        match (quote, c) {
+            _ if escaped => {
+                escaped = false;
+                current.push(c);
+            }
+            (Some(_), '\\') => {
+                escaped = true;
+                current.push(c);
+            }
+            (Some(open), c) if c == open => {
+                quote = None;
+                current.push(c);
+            }
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, ',') => arguments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err("unterminated quote".to_owned());
+    }
+
+    arguments.push(current);
+    let arguments: Vec<String> = arguments
+        .into_iter()
+        .map(|argument| argument.trim().to_owned())
+        .collect();
+
+    if arguments.len() == 1 && arguments[0].is_empty() {
+        Ok(vec![])
+    } else if arguments.iter().any(|argument| argument.is_empty()) {
+        Err("empty operand".to_owned())
+    } else {
+        Ok(arguments)
+    }
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    if let Some(register) = text.strip_prefix('#') {
+        return match register.parse::<Word>() {
+            Ok(index) if (index as usize) < REGISTERS => Ok(Operand::Word(MOD + index)),
+            _ => Err(format!("`{}` is not a register (#0-#7)", text)),
+        };
+    }
+
+    if let Some(quoted) = text.strip_prefix('\'') {
+        let characters = unescape(quoted.strip_suffix('\'').ok_or("unterminated character")?)?;
+        return match characters.as_slice() {
+            [character] => Ok(Operand::Word(*character)),
+            _ => Err(format!("`{}` is not a single character", text)),
+        };
+    }
+
+    if let Some(quoted) = text.strip_prefix('"') {
+        let text = unescape(quoted.strip_suffix('"').ok_or("unterminated string")?)?;
+        return Ok(Operand::Text(text));
+    }
+
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        let parsed = match text.strip_prefix("0x") {
+            Some(hex) => Word::from_str_radix(hex, 16),
+            None => text.parse::<Word>(),
+        };
+
+        return match parsed {
+            Ok(value) if value < MOD => Ok(Operand::Word(value)),
+            _ => Err(format!("`{}` is not a literal between 0 and 32767", text)),
+        };
+    }
+
+    if text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Ok(Operand::Label(text.to_owned()))
+    } else {
+        Err(format!("unable to parse operand `{}`", text))
+    }
+}
+
+/// Reads the escapes shared by source strings and transcripts: `\n`, `\t`,
+/// `\0`, `\\`, `\'`, `\"` and `\{N}` for any value N.
+pub(crate) fn unescape(text: &str) -> Result<Vec<Word>, String> {
+    let mut words = vec![];
+    let mut characters = text.chars();
+
+    while let Some(c) = characters.next() {
+        let character = if c == '\\' {
+            match characters.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ '\\') | Some(c @ '\'') | Some(c @ '"') => c,
+                Some('{') => {
+                    let number: String = characters.by_ref().take_while(|&c| c != '}').collect();
+                    match number.parse::<Word>() {
+                        Ok(word) if word < MOD => words.push(word),
+                        _ => return Err(format!("`\\{{{}}}` is not a word", number)),
+                    }
+                    continue;
+                }
+                other => return Err(format!("unknown escape `\\{}`", other.unwrap_or(' '))),
+            }
+        } else {
+            c
+        };
+
+        if character as u32 >= MOD as u32 {
+            return Err(format!("character `{}` does not fit in a word", character));
+        }
+        words.push(character as Word);
+    }
+
+    Ok(words)
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{State, VM};
+
+    #[test]
+    fn spec_example_test() {
+        let assembly = assemble("add(#0, #1, 4)\nout(#0)").unwrap();
+        assert_eq!(assembly.words, vec![9, 32768, 32769, 4, 19, 32768]);
+    }
+
+    #[test]
+    fn labels_and_strings_test() {
+        let source = "
+            ; count #0 down from 3, printing a star each time
+                setr(#0, 3)
+            loop:
+                out('*')
+                add(#0, #0, 32767)
+                jt(#0, loop)
+                out(\"!\\n\")
+                call(done)
+            done: halt
+            table: data 1, 'a', \"hi\", table
+        ";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.labels["loop"], 3);
+        assert_eq!(assembly.labels["done"], 18);
+        assert_eq!(&assembly.words[19..], &[1, 97, 104, 105, 19]);
+
+        let mut vm = VM::new(assembly.words);
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.get_output(), "***!\n");
+    }
+
+    #[test]
+    fn disassembler_syntax_test() {
+        let source = "0: noop\n1: out(W)\n3: out( )\n5: jif(#1, 0)\n8: ret";
+        let assembly = assemble_with(source, Syntax::Original).unwrap();
+        assert_eq!(assembly.words, vec![21, 19, 87, 19, 32, 8, 32769, 0, 18]);
+    }
+
+    #[test]
+    fn bare_operand_test() {
+        let assembly = assemble("out(5)\nout(10)\nout('5')\na: out(a)").unwrap();
+        assert_eq!(assembly.words, vec![19, 5, 19, 10, 19, 53, 19, 6]);
+        assert_eq!(
+            assemble("out(W)").unwrap_err().message,
+            "undefined label `W`"
+        );
+    }
+
+    #[test]
+    fn raw_character_test() {
+        let source = "out(,)\nout(;) ; semicolon\nout(')\nout(\")\nout())\nout(\n)\nout(#)";
+        let assembly = assemble_with(source, Syntax::Original).unwrap();
+        let characters: Vec<Word> = assembly.words.chunks(2).map(|pair| pair[1]).collect();
+        assert_eq!(
+            characters,
+            ",;'\")\n#".bytes().map(Word::from).collect::<Vec<_>>()
+        );
+    }
+
+    /// The listing the original linear disassembler printed for challenge.bin.
+    #[test]
+    fn original_listing_test() {
+        // It printed `out` of a register as `out(ERROR)`; these are all `#0`.
+        let source = include_str!("../challenge.asm").replace("out(ERROR)", "out(#0)");
+        let assembly = assemble_with(&source, Syntax::Original).unwrap();
+        let binary = include_bytes!("../challenge.bin");
+        let words: Vec<Word> = binary
+            .chunks(2)
+            .map(|pair| Word::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+
+        // It also printed only the low byte of `out` literals, which loses
+        // the 978 at address 940.
+        let differences: Vec<usize> = (0..words.len())
+            .filter(|&address| assembly.words.get(address) != Some(&words[address]))
+            .collect();
+        assert_eq!(assembly.words.len(), words.len());
+        assert_eq!(differences, vec![940]);
+        assert_eq!((assembly.words[940], words[940]), (978 % 256, 978));
+    }
+
+    #[test]
+    fn error_test() {
+        let error = |source: &str| assemble(source).unwrap_err();
+
+        assert_eq!(error("noop\nfrob(1)").line, 2);
+        assert_eq!(error("jmp(nowhere)").message, "undefined label `nowhere`");
+        assert_eq!(
+            error("add(#0, 1)").message,
+            "`add` takes 3 operands but was given 2"
+        );
+        assert_eq!(
+            error("setr(1, 2)").message,
+            "the first operand of `setr` must be a register"
+        );
+        assert_eq!(
+            error("5: halt").message,
+            "expected address 5 but this is address 0"
+        );
+        assert_eq!(
+            error("push(32768)").message,
+            "`32768` is not a literal between 0 and 32767"
+        );
+        assert_eq!(error("push(#8)").message, "`#8` is not a register (#0-#7)");
+        assert_eq!(
+            error("out(\"\\{32768}\")").message,
+            "`\\{32768}` is not a word"
+        );
+    }
+}
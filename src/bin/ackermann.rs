@@ -1,43 +1,67 @@
-use std::collections::HashMap;
+use std::env;
 use std::io;
-use std::io::prelude::*;
-
-const MODULO: usize = 32768;
-
-fn ackermann(m: usize, n: usize, k: usize, memo: &mut HashMap<(usize, usize), usize>) -> usize {
-    if let Some(value) = memo.get(&(m, n)) {
-        *value
-    } else {
-        let result = if m == 0 {
-            (n + 1) % MODULO
-        } else {
-            if n == 0 {
-                ackermann(m - 1, k, k, memo)
-            } else {
-                let x = ackermann(m, n - 1, k, memo);
-                ackermann(m - 1, x, k, memo)
-            }
-        };
-        memo.insert((m, n), result);
-        result
-    }
+use synacor_challenge::ackermann::Confirmation;
+use synacor_challenge::program::{Program, Strictness};
+use synacor_challenge::signature::locate_teleporter;
+
+const USAGE: &str = "usage: ackermann [<challenge.bin>] [--m <m>] [--n <n>] [--modulus <modulus>] \
+                     [--expected <value>] [--threads <count>]";
+
+fn usage() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
 }
 
-fn main() -> std::io::Result<()> {
-    let m = 4;
-    let n = 1;
+fn number<T: std::str::FromStr>(value: Option<String>) -> io::Result<T> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(usage)
+}
 
-    for k in 1..32768 {
-        let mut memo: HashMap<(usize, usize), usize> = HashMap::new();
+fn main() -> io::Result<()> {
+    let mut confirmation = Confirmation {
+        m: 4,
+        n: 1,
+        modulus: 32_768,
+        expected: 6,
+    };
+    let mut threads: Option<usize> = None;
+    let mut args = env::args().skip(1);
 
-        if ackermann(m, n, k, &mut memo) == 6 {
-            println!("\nk={}", k);
-            break;
-        } else {
-            print!(".");
-            io::stdout().flush().unwrap();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--m" => confirmation.m = number(args.next())?,
+            "--n" => confirmation.n = number(args.next())?,
+            "--modulus" => confirmation.modulus = number(args.next())?,
+            "--expected" => confirmation.expected = number(args.next())?,
+            "--threads" => threads = Some(number(args.next())?),
+            _ if arg.starts_with("--") => return Err(usage()),
+            _ => {
+                // Take the parameters from the binary's own call.
+                let program = Program::load(&arg, Strictness::Lenient)?;
+                let teleporter = locate_teleporter(program.words())
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                confirmation = teleporter.confirmation();
+            }
         }
     }
 
+    println!(
+        "Solving A({}, {}) = {} modulo {}...",
+        confirmation.m, confirmation.n, confirmation.expected, confirmation.modulus
+    );
+    let solution = match threads {
+        Some(threads) => confirmation.solve_with_threads(threads),
+        None => confirmation.solve(),
+    }
+    .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+
+    for value in &solution.values {
+        println!("k={}", value);
+    }
+    println!(
+        "{} matching values in {:.2?} on {} threads",
+        solution.values.len(),
+        solution.elapsed,
+        solution.threads
+    );
+
     Ok(())
 }
@@ -0,0 +1,38 @@
+use std::env;
+use std::io;
+use synacor_challenge::assembler::{assemble_with, Syntax};
+use synacor_challenge::program::Program;
+
+fn main() -> std::io::Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let syntax = match args.iter().position(|arg| arg == "--original") {
+        Some(index) => {
+            args.remove(index);
+            Syntax::Original
+        }
+        None => Syntax::Listing,
+    };
+
+    if args.len() != 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: assembler [--original] <input.asm> <output.bin>",
+        ));
+    }
+
+    let source = std::fs::read_to_string(&args[0])?;
+    let assembly = assemble_with(&source, syntax).map_err(|error| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}", args[0], error))
+    })?;
+
+    let program = Program::from_words(assembly.words);
+    std::fs::write(&args[1], program.to_bytes())?;
+    println!(
+        "Wrote {} words ({} labels) to `{}`",
+        program.len(),
+        assembly.labels.len(),
+        args[1]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,631 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use synacor_challenge::decoder::decode;
+use synacor_challenge::patch::Patch;
+use synacor_challenge::program::{Program, Strictness};
+use synacor_challenge::signature::locate_teleporter;
+use synacor_challenge::symbols::Symbols;
+use synacor_challenge::vm::{
+    Operation, Param, State, Word, MNEMONICS, REGISTER_LIMIT, VM, WORD_LIMIT,
+};
+
+const HELP: &str = "\
+addresses may be given by symbol name, registers by number or name
+
+break|b <addr>        break when execution reaches <addr>
+delete|d <addr>       remove the breakpoint at <addr>
+breakop <op>          break on an opcode (number or mnemonic, e.g. `call`)
+deleteop <op>         remove an opcode breakpoint
+watch <addr>          break when `wmem` writes to <addr>
+unwatch <addr>        remove a memory watchpoint
+watchreg <r>          break when register <r> changes
+unwatchreg <r>        remove a register watchpoint
+info                  list breakpoints and watchpoints
+continue|c            run until a breakpoint, watchpoint or input is needed
+step|s [n]            execute <n> instructions (default 1)
+next|n                step over calls
+finish                run until the current function returns
+record [limit]        record history for reverse execution (default 1000000)
+rstep|rs [n]          undo <n> instructions (default 1)
+rcontinue|rc <addr>   run backwards until execution is about to reach <addr>
+rwatch <addr>         run backwards to the last write of <addr>
+backtrace|bt          show the call stack
+where|w               show the current instruction
+regs                  show the registers
+setreg <r> <value>    set register <r>
+mem|x <addr> [count]  show memory
+setmem <addr> <v>...  write words starting at <addr>
+stack                 show the stack
+setstack <i> <value>  overwrite stack entry <i> (0 is the bottom)
+push <value>          push onto the stack
+pop                   pop from the stack
+jump <addr>           move the IP to <addr>
+patch <path>          apply a patch file, checking the original words first
+unpatch <path>        revert a patch file
+> <text>              send a line of input to the game
+quit|q                exit the debugger";
+
+const RECORD_LIMIT: usize = 1_000_000;
+
+struct Frame {
+    call_site: usize,
+    /// Unknown for calls through a register that weren't seen being made.
+    target: Option<usize>,
+    return_address: usize,
+    /// The stack depth with the return address pushed.
+    base: usize,
+}
+
+enum Mode {
+    Continue,
+    Step(usize),
+    Next,
+    Finish,
+}
+
+enum Stop {
+    Breakpoint(usize),
+    Opcode(&'static str),
+    MemoryWatch(usize, Word, Word),
+    RegisterWatch(usize, Word, Word),
+    Stepped,
+    Finished,
+    Stopped(State),
+}
+
+struct Debugger {
+    vm: VM,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: BTreeSet<Word>,
+    memory_watches: BTreeSet<usize>,
+    register_watches: BTreeSet<usize>,
+    frames: Vec<Frame>,
+    symbols: Symbols,
+}
+
+impl Debugger {
+    fn new(vm: VM, symbols: Symbols) -> Self {
+        Self {
+            vm,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            memory_watches: BTreeSet::new(),
+            register_watches: BTreeSet::new(),
+            frames: vec![],
+        }
+    }
+
+    fn address(&self, args: &[&str], index: usize) -> Result<usize, String> {
+        match args.get(index).and_then(|arg| self.symbols.address_of(arg)) {
+            Some(address) => Ok(address),
+            None => number(args, index),
+        }
+    }
+
+    fn register(&self, args: &[&str], index: usize) -> Result<usize, String> {
+        match args
+            .get(index)
+            .and_then(|arg| self.symbols.register_of(arg))
+        {
+            Some(register) => Ok(register),
+            None => register(args, index),
+        }
+    }
+
+    fn register_name(&self, index: usize) -> String {
+        match self.symbols.get_register_name(index) {
+            Some(name) => format!("#{} ({})", index, name),
+            None => format!("#{}", index),
+        }
+    }
+
+    fn resolve(&self, param: Param) -> Word {
+        match param {
+            Param::Literal(value) => value,
+            Param::Register(index) => self.vm.get_registers()[index],
+        }
+    }
+
+    fn execute(&mut self, mode: Mode) -> Stop {
+        let start_depth = self.frames.len();
+        let mut executed: usize = 0;
+
+        loop {
+            let ip = self.vm.get_ip();
+            let peeked = self.vm.peek_operation().ok();
+
+            if executed > 0 {
+                if self.breakpoints.contains(&ip) {
+                    return Stop::Breakpoint(ip);
+                }
+
+                if let Some((operation, _)) = &peeked {
+                    if self.opcode_breakpoints.contains(&operation.opcode()) {
+                        return Stop::Opcode(operation.mnemonic());
+                    }
+                }
+
+                match mode {
+                    Mode::Step(count) if executed >= count => return Stop::Stepped,
+                    Mode::Next if self.frames.len() <= start_depth => return Stop::Stepped,
+                    Mode::Finish if self.frames.len() < start_depth => return Stop::Finished,
+                    _ => {}
+                }
+            }
+
+            let registers = self.vm.get_registers().to_vec();
+            let depth = self.vm.get_stack().len();
+            let written = match &peeked {
+                Some((Operation::WriteMemory(address, _), _)) => {
+                    let address = self.resolve(*address) as usize;
+                    Some((address, self.vm.get_memory().get(address).copied()))
+                }
+                _ => None,
+            };
+
+            let state = self.vm.step();
+            self.update_frames(depth, ip, &peeked);
+            if state != State::Paused {
+                return Stop::Stopped(state);
+            }
+            executed += 1;
+
+            if let Some((address, Some(old))) = written {
+                if self.memory_watches.contains(&address) {
+                    return Stop::MemoryWatch(address, old, self.vm.get_memory()[address]);
+                }
+            }
+
+            for &index in &self.register_watches {
+                let new = self.vm.get_registers()[index];
+                if new != registers[index] {
+                    return Stop::RegisterWatch(index, registers[index], new);
+                }
+            }
+        }
+    }
+
+    /// Follows the VM's stack after the instruction at `ip`, given the
+    /// stack's size beforehand. Hooked and memoized calls push nothing, so
+    /// they get no frame.
+    fn update_frames(&mut self, depth: usize, ip: usize, executed: &Option<(Operation, usize)>) {
+        let stack = self.vm.get_stack();
+        let unchanged = depth.min(stack.len());
+        while self
+            .frames
+            .last()
+            .is_some_and(|frame| frame.base > unchanged)
+        {
+            self.frames.pop();
+        }
+
+        if let Some((Operation::Call(_), return_address)) = *executed {
+            if stack.len() == depth + 1 && stack[depth] as usize == return_address {
+                self.frames.push(Frame {
+                    call_site: ip,
+                    target: Some(self.vm.get_ip()),
+                    return_address,
+                    base: depth + 1,
+                });
+            }
+        }
+    }
+
+    /// Rebuilds the call frames from the whole stack, for commands that move
+    /// around in time or edit the stack or code directly. Frames still in
+    /// place are kept; other entries are guessed at with `infer_frame`.
+    fn rebuild_frames(&mut self) {
+        let mut known = std::mem::take(&mut self.frames).into_iter().peekable();
+
+        for index in 0..self.vm.get_stack().len() {
+            while known.peek().is_some_and(|frame| frame.base <= index) {
+                known.next();
+            }
+            if let Some(frame) = self.infer_frame(index) {
+                match known
+                    .next_if(|known| known.base == frame.base && known.call_site == frame.call_site)
+                {
+                    Some(known) => self.frames.push(known),
+                    None => self.frames.push(frame),
+                }
+            }
+        }
+    }
+
+    /// A stack entry is taken to be a return address when the instruction
+    /// just before the address it holds is a `call`.
+    fn infer_frame(&self, index: usize) -> Option<Frame> {
+        let return_address = self.vm.get_stack()[index] as usize;
+        let call_site = return_address.checked_sub(2)?;
+        let target = match decode(self.vm.get_memory(), call_site).ok()? {
+            (Operation::Call(Param::Literal(target)), next) if next == return_address => {
+                Some(target as usize)
+            }
+            (Operation::Call(Param::Register(_)), next) if next == return_address => None,
+            _ => return None,
+        };
+
+        Some(Frame {
+            call_site,
+            target,
+            return_address,
+            base: index + 1,
+        })
+    }
+
+    fn report(&mut self, stop: Stop) {
+        let output = self.vm.get_output();
+        if !output.is_empty() {
+            print!("{}", output);
+        }
+
+        match stop {
+            Stop::Breakpoint(address) => {
+                println!("breakpoint at {}", self.symbols.describe(address))
+            }
+            Stop::Opcode(mnemonic) => println!("opcode breakpoint on `{}`", mnemonic),
+            Stop::MemoryWatch(address, old, new) => {
+                println!("memory watchpoint: [{}] {} -> {}", address, old, new)
+            }
+            Stop::RegisterWatch(index, old, new) => {
+                println!(
+                    "register watchpoint: {} {} -> {}",
+                    self.register_name(index),
+                    old,
+                    new
+                )
+            }
+            Stop::Stepped | Stop::Finished => {}
+            Stop::Stopped(State::WaitingForInput) => {
+                println!("waiting for input (send it with `> <text>`)")
+            }
+            Stop::Stopped(state) => println!("stopped: {:?}", state),
+        }
+
+        self.print_current();
+    }
+
+    fn report_reverse(&self, found: bool) {
+        if self.vm.get_undo_log().is_none() {
+            println!("not recording (start with `record`)");
+        } else if !found {
+            println!("reached the start of the recorded history");
+        }
+
+        self.print_current();
+    }
+
+    fn print_current(&self) {
+        let ip = self.symbols.describe(self.vm.get_ip());
+
+        if let Some(comment) = self.symbols.get_comment(self.vm.get_ip()) {
+            println!("; {}", comment);
+        }
+        match self.vm.peek_operation() {
+            Ok((operation, _)) => println!("{}: {:?}", ip, operation),
+            Err(error) => println!("{}: {}", ip, error),
+        }
+    }
+
+    fn print_info(&self) {
+        println!(
+            "breakpoints: {:?}",
+            self.breakpoints
+                .iter()
+                .map(|&address| self.symbols.describe(address))
+                .collect::<Vec<_>>()
+        );
+        println!(
+            "opcode breakpoints: {:?}",
+            self.opcode_breakpoints
+                .iter()
+                .map(|&opcode| MNEMONICS[opcode as usize])
+                .collect::<Vec<_>>()
+        );
+        println!("memory watchpoints: {:?}", self.memory_watches);
+        println!("register watchpoints: {:?}", self.register_watches);
+    }
+
+    fn print_backtrace(&self) {
+        if self.frames.is_empty() {
+            println!("no call frames recorded");
+        }
+
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            println!(
+                "#{} {} called from {} (returns to {})",
+                depth,
+                frame.target.map_or_else(
+                    || "<indirect>".to_owned(),
+                    |target| self.symbols.describe(target)
+                ),
+                frame.call_site,
+                frame.return_address
+            );
+        }
+    }
+
+    fn print_memory(&self, address: usize, count: usize) {
+        let memory = self.vm.get_memory();
+        let end = address.saturating_add(count).min(memory.len());
+
+        for (row, chunk) in memory[address.min(end)..end].chunks(8).enumerate() {
+            let words: Vec<String> = chunk.iter().map(|word| format!("{:5}", word)).collect();
+            println!("{:5}: {}", address + row * 8, words.join(" "));
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        if let Some(text) = line.strip_prefix('>') {
+            for &byte in text.trim_start().as_bytes() {
+                self.vm.add_input(byte as Word);
+            }
+            self.vm.add_input(b'\n' as Word);
+            return Ok(true);
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let args = if words.is_empty() {
+            &words[..]
+        } else {
+            &words[1..]
+        };
+
+        let name = words.first().copied().unwrap_or("");
+        match name {
+            "" => {}
+            "help" | "h" => println!("{}", HELP),
+            "break" | "b" => {
+                self.breakpoints.insert(self.address(args, 0)?);
+            }
+            "delete" | "d" => {
+                self.breakpoints.remove(&self.address(args, 0)?);
+            }
+            "breakop" => {
+                self.opcode_breakpoints.insert(opcode(args)?);
+            }
+            "deleteop" => {
+                self.opcode_breakpoints.remove(&opcode(args)?);
+            }
+            "watch" => {
+                self.memory_watches.insert(self.address(args, 0)?);
+            }
+            "unwatch" => {
+                self.memory_watches.remove(&self.address(args, 0)?);
+            }
+            "watchreg" => {
+                self.register_watches.insert(self.register(args, 0)?);
+            }
+            "unwatchreg" => {
+                self.register_watches.remove(&self.register(args, 0)?);
+            }
+            "info" => self.print_info(),
+            "continue" | "c" => {
+                let stop = self.execute(Mode::Continue);
+                self.report(stop);
+            }
+            "step" | "s" => {
+                let count = if args.is_empty() { 1 } else { number(args, 0)? };
+                let stop = self.execute(Mode::Step(count));
+                self.report(stop);
+            }
+            "next" | "n" => {
+                let stop = self.execute(Mode::Next);
+                self.report(stop);
+            }
+            "finish" => {
+                if self.frames.is_empty() {
+                    return Err("no call frame to finish".to_owned());
+                }
+                let stop = self.execute(Mode::Finish);
+                self.report(stop);
+            }
+            "record" => {
+                let limit = if args.is_empty() {
+                    RECORD_LIMIT
+                } else {
+                    number(args, 0)?
+                };
+                self.vm.enable_undo(limit);
+                println!("recording up to {} instructions", limit);
+            }
+            "rstep" | "rs" => {
+                let count = if args.is_empty() { 1 } else { number(args, 0)? };
+                let stepped = (0..count).take_while(|_| self.vm.step_back()).count();
+                self.report_reverse(stepped == count);
+            }
+            "rcontinue" | "rc" => {
+                let found = self.vm.run_back_to(self.address(args, 0)?);
+                self.report_reverse(found);
+            }
+            "rwatch" => {
+                let found = self.vm.run_back_to_write(self.address(args, 0)?);
+                self.report_reverse(found);
+            }
+            "backtrace" | "bt" => self.print_backtrace(),
+            "where" | "w" => self.print_current(),
+            "regs" => {
+                for (index, value) in self.vm.get_registers().iter().enumerate() {
+                    println!("{} = {}", self.register_name(index), value);
+                }
+                println!(
+                    "ip = {}  cycles = {}",
+                    self.vm.get_ip(),
+                    self.vm.get_cycles()
+                );
+            }
+            "setreg" => {
+                let index = self.register(args, 0)?;
+                self.vm.set_register(index, value(args, 1, REGISTER_LIMIT)?);
+            }
+            "mem" | "x" => {
+                let count = if args.len() > 1 { number(args, 1)? } else { 8 };
+                self.print_memory(self.address(args, 0)?, count);
+            }
+            "setmem" => {
+                let address = self.address(args, 0)?;
+                for index in 1..args.len().max(2) {
+                    if !self
+                        .vm
+                        .set_memory(address + index - 1, value(args, index, WORD_LIMIT)?)
+                    {
+                        return Err(format!("address {} is out of range", address + index - 1));
+                    }
+                }
+            }
+            "stack" => println!("{:?}", self.vm.get_stack()),
+            "setstack" => {
+                let index = number(args, 0)?;
+                let value = value(args, 1, WORD_LIMIT)?;
+                match self.vm.get_stack_mut().get_mut(index) {
+                    Some(entry) => *entry = value,
+                    None => return Err(format!("stack has no entry {}", index)),
+                }
+            }
+            "push" => {
+                let value = value(args, 0, WORD_LIMIT)?;
+                self.vm.get_stack_mut().push(value);
+            }
+            "pop" => match self.vm.get_stack_mut().pop() {
+                Some(value) => println!("{}", value),
+                None => return Err("stack is empty".to_owned()),
+            },
+            "jump" => {
+                self.vm.set_ip(self.address(args, 0)?);
+                self.print_current();
+            }
+            "patch" | "unpatch" => {
+                let path = args.first().ok_or("missing patch file")?;
+                let patch = Patch::load(path, &self.symbols)
+                    .map_err(|error| format!("{}: {}", path, error))?;
+                let result = if name == "patch" {
+                    patch.apply(&mut self.vm)
+                } else {
+                    patch.revert(&mut self.vm)
+                };
+                result.map_err(|error| format!("{}: {}", path, error))?;
+            }
+            "quit" | "q" => return Ok(false),
+            other => return Err(format!("unknown command `{}` (try `help`)", other)),
+        }
+
+        // Running forwards keeps the frames up to date; anything else may
+        // have changed the stack or code behind their back.
+        if !matches!(
+            name,
+            "continue" | "c" | "step" | "s" | "next" | "n" | "finish"
+        ) {
+            self.rebuild_frames();
+        }
+
+        Ok(true)
+    }
+}
+
+fn number(args: &[&str], index: usize) -> Result<usize, String> {
+    let arg = args
+        .get(index)
+        .ok_or_else(|| format!("missing argument {}", index + 1))?;
+
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse::<usize>(),
+    };
+
+    parsed.map_err(|_| format!("`{}` is not a number", arg))
+}
+
+fn value(args: &[&str], index: usize, limit: Word) -> Result<Word, String> {
+    match number(args, index)? {
+        value if value < limit as usize => Ok(value as Word),
+        _ => Err(format!("`{}` is not below {}", args[index], limit)),
+    }
+}
+
+fn register(args: &[&str], index: usize) -> Result<usize, String> {
+    let arg = args
+        .get(index)
+        .ok_or_else(|| format!("missing argument {}", index + 1))?;
+
+    match arg.trim_start_matches('#').parse::<usize>() {
+        Ok(register) if register < 8 => Ok(register),
+        _ => Err(format!("`{}` is not a register (0-7)", arg)),
+    }
+}
+
+fn opcode(args: &[&str]) -> Result<Word, String> {
+    let arg = args.first().ok_or("missing opcode")?;
+
+    MNEMONICS
+        .iter()
+        .position(|mnemonic| mnemonic == arg)
+        .or_else(|| arg.parse::<usize>().ok().filter(|&op| op < MNEMONICS.len()))
+        .map(|op| op as Word)
+        .ok_or_else(|| format!("`{}` is not an opcode", arg))
+}
+
+fn main() -> std::io::Result<()> {
+    let mut bin_path: Option<String> = None;
+    let mut symbols: Option<Symbols> = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--symbols" {
+            let path = args.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "--symbols needs a path")
+            })?;
+            symbols = Some(Symbols::load(path)?);
+        } else {
+            bin_path = Some(arg);
+        }
+    }
+
+    let bin_path = bin_path.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: debugger <challenge.bin> [--symbols <path>]",
+        )
+    })?;
+    println!("Loading `{}`...", bin_path);
+
+    let program = Program::load(bin_path, Strictness::Lenient)?;
+
+    for warning in program.report().warnings() {
+        eprintln!("WARNING: {}", warning);
+    }
+
+    // Without a symbols file, name what can be found by signature.
+    let symbols = match symbols {
+        Some(symbols) => symbols,
+        None => locate_teleporter(program.words())
+            .map(|teleporter| teleporter.to_symbols())
+            .unwrap_or_else(|error| {
+                eprintln!("WARNING: {}", error);
+                Symbols::new()
+            }),
+    };
+
+    let mut debugger = Debugger::new(VM::new(program), symbols);
+    debugger.print_current();
+
+    loop {
+        print!("(sdb) ");
+        std::io::stdout().flush().unwrap();
+
+        let line = match io::stdin().lock().lines().next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        match debugger.command(line.trim()) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(message) => println!("error: {}", message),
+        }
+    }
+
+    Ok(())
+}
@@ -1,86 +1,67 @@
 use std::env;
-use synacor_challenge::vm::{Operation, Param, Word, VM};
-
-fn p(param: Param) -> String {
-    match param {
-        Param::Literal(value) => format!("{}", value),
-        Param::Register(index) => format!("#{}", index),
-    }
-}
+use std::io;
+use synacor_challenge::disassembly::disassemble;
+use synacor_challenge::program::{Program, Strictness};
+use synacor_challenge::snapshot::Snapshot;
+use synacor_challenge::symbols::Symbols;
 
 fn main() -> std::io::Result<()> {
-    let bin_path: String = env::args().nth(1).unwrap();
-    let bin = std::fs::read(bin_path)?;
-    let mut bytes = bin.iter();
-    let mut memory: Vec<Word> = Vec::new();
+    let mut bin_path: Option<String> = None;
+    let mut snapshot = false;
+    let mut symbols = Symbols::new();
+    let mut entry_points: Vec<usize> = vec![0];
+    let mut args = env::args().skip(1);
 
-    while let Some(&low_byte) = bytes.next() {
-        if let Some(&high_byte) = bytes.next() {
-            memory.push(((high_byte as Word) << 8) + (low_byte as Word));
+    while let Some(arg) = args.next() {
+        if arg == "--entry" {
+            let entry = args
+                .next()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "--entry needs an address")
+                })?;
+            entry_points.push(entry);
+        } else if arg == "--symbols" {
+            let path = args.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "--symbols needs a path")
+            })?;
+            symbols = Symbols::load(path)?;
+        } else if arg == "--snapshot" {
+            snapshot = true;
+        } else {
+            bin_path = Some(arg);
         }
     }
 
-    let mut vm = VM::new(memory);
+    let bin_path = bin_path.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: disassembler <challenge.bin> [--snapshot] [--symbols <path>] [--entry <address>]...",
+        )
+    })?;
 
-    loop {
-        print!("{}: ", vm.get_ip());
-        match vm.get_next_operation() {
-            Ok(operation) => {
-                match operation {
-                    Operation::Halt => println!("halt"),
-                    Operation::SetRegister(register, value) => {
-                        println!("setr({}, {})", p(register), p(value))
-                    }
-                    Operation::Push(value) => println!("push({})", p(value)),
-                    Operation::Pop(output) => println!("pop({})", p(output)),
-                    Operation::Equal(output, a, b) => {
-                        println!("eq({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::GreaterThan(output, a, b) => {
-                        println!("gt({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::Jump(to) => println!("jmp({})", p(to)),
-                    Operation::JumpIfTrue(condition, to) => {
-                        println!("jit({}, {})", p(condition), p(to))
-                    }
-                    Operation::JumpIfFalse(condition, to) => {
-                        println!("jif({}, {})", p(condition), p(to))
-                    }
-                    Operation::Add(output, a, b) => {
-                        println!("add({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::Mult(output, a, b) => {
-                        println!("mul({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::Mod(output, a, b) => {
-                        println!("mod({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::And(output, a, b) => {
-                        println!("and({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::Or(output, a, b) => {
-                        println!("or({}, {}, {})", p(output), p(a), p(b))
-                    }
-                    Operation::Not(output, a) => println!("not({}, {})", p(output), p(a)),
-                    Operation::ReadMemory(output, location) => {
-                        println!("rmem({}, {})", p(output), p(location))
-                    }
-                    Operation::WriteMemory(output, value) => {
-                        println!("wmem({}, {})", p(output), p(value))
-                    }
-                    Operation::Call(to) => println!("call({})", p(to)),
-                    Operation::Return => println!("ret"),
-                    Operation::Out(Param::Literal(value)) => {
-                        println!("out({})", (value as u8 as char))
-                    }
-                    Operation::Out(_) => println!("out(ERROR)"),
-                    Operation::In(output) => println!("in({})", p(output)),
-                    Operation::NoOp => println!("noop"),
-                };
-            }
-            Err(message) => println!("ERROR: {}", message),
+    // The game decrypts its text at runtime, so a snapshot taken after the
+    // self-test shows far more of it than the binary does.
+    let memory = if snapshot {
+        Snapshot::load(bin_path)?.memory
+    } else {
+        let program = Program::load(bin_path, Strictness::Lenient)?;
+        for warning in program.report().warnings() {
+            eprintln!("WARNING: {}", warning);
         }
-    }
+        program.into_words()
+    };
 
-    Ok(())
+    entry_points.extend(symbols.code_addresses());
+    let mut disassembly = disassemble(&memory, &entry_points);
+    for &address in &disassembly.unresolved {
+        eprintln!(
+            "WARNING: {} at {} jumps through a register; code it reaches needs --entry",
+            disassembly.instructions[&address].operation.mnemonic(),
+            address
+        );
+    }
+    disassembly.set_symbols(symbols);
+    let stdout = io::stdout();
+    disassembly.write_listing(&mut stdout.lock())
 }
@@ -0,0 +1,79 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use synacor_challenge::control_flow::analyze;
+use synacor_challenge::disassembly::disassemble;
+use synacor_challenge::program::{Program, Strictness};
+use synacor_challenge::snapshot::Snapshot;
+use synacor_challenge::symbols::Symbols;
+
+const USAGE: &str =
+    "usage: flowgraph <challenge.bin> [--snapshot] [--symbols <path>] [--entry <address>]... \
+                     [--format calls|cfg|json] [--function <address>]... [--output <path>]";
+
+fn usage() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+fn address(value: Option<String>) -> io::Result<usize> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(usage)
+}
+
+fn main() -> io::Result<()> {
+    let mut bin_path: Option<String> = None;
+    let mut snapshot = false;
+    let mut symbols = Symbols::new();
+    let mut entry_points: Vec<usize> = vec![0];
+    let mut format = "calls".to_owned();
+    let mut functions: Vec<usize> = vec![];
+    let mut output: Option<String> = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--snapshot" => snapshot = true,
+            "--symbols" => symbols = Symbols::load(args.next().ok_or_else(usage)?)?,
+            "--entry" => entry_points.push(address(args.next())?),
+            "--format" => format = args.next().ok_or_else(usage)?,
+            "--function" => functions.push(address(args.next())?),
+            "--output" => output = Some(args.next().ok_or_else(usage)?),
+            _ => bin_path = Some(arg),
+        }
+    }
+
+    let bin_path = bin_path.ok_or_else(usage)?;
+    let memory = if snapshot {
+        Snapshot::load(bin_path)?.memory
+    } else {
+        let program = Program::load(bin_path, Strictness::Lenient)?;
+        for warning in program.report().warnings() {
+            eprintln!("WARNING: {}", warning);
+        }
+        program.into_words()
+    };
+
+    entry_points.extend(symbols.code_addresses());
+    let mut disassembly = disassemble(&memory, &entry_points);
+    disassembly.set_symbols(symbols);
+    let control_flow = analyze(&disassembly, &entry_points);
+
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+
+    match format.as_str() {
+        "calls" => control_flow.write_call_graph_dot(&disassembly, &mut out)?,
+        "cfg" => {
+            if functions.is_empty() {
+                functions = control_flow.functions.keys().copied().collect();
+            }
+            control_flow.write_cfg_dot(&disassembly, &functions, &mut out)?
+        }
+        "json" => control_flow.write_json(&disassembly, &mut out)?,
+        _ => return Err(usage()),
+    }
+
+    out.flush()
+}
@@ -1,6 +1,17 @@
 use std::env;
 use std::io;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::str::FromStr;
+use synacor_challenge::patch::Patch;
+use synacor_challenge::program::{Program, Strictness};
+use synacor_challenge::script::Script;
+use synacor_challenge::signature::locate_teleporter;
+use synacor_challenge::snapshot::Snapshot;
+use synacor_challenge::streams::{MemoryOutput, OutputDecoding, TeeSink, WriterSink};
+use synacor_challenge::symbols::Symbols;
+use synacor_challenge::trace::{TraceFilter, TraceFormat, Tracer};
+use synacor_challenge::transcript::{replay, Transcript};
 use synacor_challenge::vm::{State, Word, VM};
 
 fn add_line_of_input(vm: &mut VM, line: &str) {
@@ -10,144 +21,234 @@ fn add_line_of_input(vm: &mut VM, line: &str) {
     vm.add_input(b'\n' as Word);
 }
 
-fn main() -> std::io::Result<()> {
-    let bin_path: String = env::args().nth(1).unwrap();
-    println!("Loading `{}`...", bin_path);
+struct Options {
+    bin_path: String,
+    trace_path: Option<String>,
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+    symbols_path: Option<String>,
+    output_decoding: OutputDecoding,
+    scripts: Vec<Script>,
+    patch_paths: Vec<String>,
+    memoize: bool,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+}
+
+fn parse_range<T: FromStr>(text: &str) -> Result<Range<T>, String> {
+    let error = || format!("`{}` is not a range like 100..200", text);
+    let mut bounds = text.splitn(2, "..");
+    let start = bounds
+        .next()
+        .ok_or_else(error)?
+        .parse()
+        .map_err(|_| error())?;
+    let end = bounds
+        .next()
+        .ok_or_else(error)?
+        .parse()
+        .map_err(|_| error())?;
+
+    Ok(start..end)
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        bin_path: String::new(),
+        trace_path: None,
+        trace_format: TraceFormat::Text,
+        trace_filter: TraceFilter::default(),
+        symbols_path: None,
+        output_decoding: OutputDecoding::Lossy,
+        scripts: vec![],
+        patch_paths: vec![],
+        memoize: false,
+        record_path: None,
+        replay_path: None,
+    };
 
-    let bin = std::fs::read(bin_path)?;
-    let mut bytes = bin.iter();
-    let mut memory: Vec<Word> = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
 
-    while let Some(&low_byte) = bytes.next() {
-        if let Some(&high_byte) = bytes.next() {
-            memory.push(((high_byte as Word) << 8) + (low_byte as Word));
+        match arg.as_str() {
+            "--trace" => options.trace_path = Some(value()?),
+            "--trace-format" => {
+                options.trace_format = match value()?.as_str() {
+                    "text" => TraceFormat::Text,
+                    "json" => TraceFormat::Json,
+                    other => return Err(format!("unknown trace format `{}`", other)),
+                }
+            }
+            "--trace-addresses" => options.trace_filter.addresses = Some(parse_range(&value()?)?),
+            "--trace-cycles" => options.trace_filter.cycles = Some(parse_range(&value()?)?),
+            "--symbols" => options.symbols_path = Some(value()?),
+            "--script" => {
+                let path = value()?;
+                let script = Script::load(&path).map_err(|error| format!("{}: {}", path, error))?;
+                options.scripts.push(script);
+            }
+            "--patch" => options.patch_paths.push(value()?),
+            "--output-decoding" => {
+                options.output_decoding = match value()?.as_str() {
+                    "ascii" => OutputDecoding::Ascii,
+                    "latin1" => OutputDecoding::Latin1,
+                    "lossy" => OutputDecoding::Lossy,
+                    "numeric" => OutputDecoding::Numeric,
+                    other => return Err(format!("unknown output decoding `{}`", other)),
+                }
+            }
+            "--memoize" => options.memoize = true,
+            "--record" => options.record_path = Some(value()?),
+            "--replay" => options.replay_path = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ => options.bin_path = arg,
         }
     }
 
-    let mut vm = VM::new(memory);
-
-    for line in vec![
-        "take tablet",
-        "use tablet",
-        "doorway",
-        "north",
-        "north",
-        "bridge",
-        "continue",
-        "down",
-        "east",
-        "take empty lantern",
-        "west",
-        "west",
-        "passage",
-        "ladder",
-        "west",
-        "south",
-        "north",
-        "take can",
-        "use can",
-        "west",
-        "ladder",
-        "darkness",
-        "use lantern",
-        "continue",
-        "west",
-        "west",
-        "west",
-        "west",
-        "north",
-        "take red coin",
-        "look red coin",
-        "north",
-        "east",
-        "take concave coin",
-        "look concave coin",
-        "west",
-        "west",
-        "take blue coin",
-        "look blue coin",
-        "east",
-        "west",
-        "up",
-        "take shiny coin",
-        "look shiny coin",
-        "down",
-        "east",
-        "east",
-        "down",
-        "take corroded coin",
-        "look corroded coin",
-        "up",
-        "west",
-        "use blue coin",
-        "use red coin",
-        "use shiny coin",
-        "use concave coin",
-        "use corroded coin",
-        "north",
-        "take teleporter",
-        "look teleporter",
-        "use teleporter",
-        "take business card",
-        "look business card",
-        "take strange book",
-        "look strange book",
-    ] {
-        add_line_of_input(&mut vm, line);
+    if options.bin_path.is_empty() {
+        return Err("usage: interactive <challenge.bin> [--trace <path>] [--trace-format text|json] [--trace-addresses a..b] [--trace-cycles a..b] [--symbols <path>] [--script <path>]... [--patch <path>]... [--memoize] [--output-decoding ascii|latin1|lossy|numeric] [--record <path>] [--replay <path>]".to_owned());
+    }
+
+    Ok(options)
+}
+
+fn run_command(vm: &mut VM, line: &str) -> bool {
+    if let Some(path) = line.strip_prefix("save ") {
+        match vm.snapshot().save(path.trim()) {
+            Ok(()) => println!("Saved snapshot to `{}`.", path.trim()),
+            Err(error) => println!("Unable to save snapshot: {}", error),
+        }
+        true
+    } else if let Some(path) = line.strip_prefix("load ") {
+        match Snapshot::load(path.trim()) {
+            Ok(snapshot) => {
+                vm.restore(snapshot);
+                println!("Loaded snapshot from `{}`.", path.trim());
+            }
+            Err(error) => println!("Unable to load snapshot: {}", error),
+        }
+        true
+    } else {
+        false
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut options =
+        parse_options().map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    println!("Loading `{}`...", options.bin_path);
+
+    let program = Program::load(&options.bin_path, Strictness::Lenient)?;
+
+    for warning in program.report().warnings() {
+        eprintln!("WARNING: {}", warning);
+    }
+
+    // Without a symbols file, name what can be found by signature so patches
+    // written against `challenge.sym` names work on any variant.
+    let symbols = match &options.symbols_path {
+        Some(path) => Symbols::load(path)?,
+        None => locate_teleporter(program.words())
+            .map(|teleporter| teleporter.to_symbols())
+            .unwrap_or_else(|error| {
+                eprintln!("WARNING: {}", error);
+                Symbols::new()
+            }),
+    };
+
+    if let Some(path) = &options.replay_path {
+        let transcript = Transcript::load(path)?;
+        return match replay(&program.into_words(), &transcript) {
+            Ok(result) => {
+                println!(
+                    "Replayed `{}`: {} output words matched over {} cycles.",
+                    path, result.output, result.cycles
+                );
+                Ok(())
+            }
+            Err(error) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("replay of `{}` failed: {}", path, error),
+            )),
+        };
+    }
+
+    let mut vm = VM::new(program);
+    if options.record_path.is_some() {
+        vm.set_transcript(Transcript::new());
+    }
+    if options.memoize {
+        vm.enable_memoization();
+    }
+    for path in &options.patch_paths {
+        Patch::load(path, &symbols)
+            .and_then(|patch| patch.apply(&mut vm))
+            .map_err(|error| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, error))
+            })?;
+    }
+    // Scripts check the game's replies, so output is kept as well as printed.
+    vm.set_output(TeeSink::new(
+        MemoryOutput::new(),
+        WriterSink::stdout().with_decoding(options.output_decoding),
+    ));
+
+    if let Some(trace_path) = &options.trace_path {
+        let tracer = Tracer::create(trace_path, options.trace_format)?;
+        vm.set_tracer(
+            tracer
+                .with_filter(options.trace_filter.clone())
+                .with_symbols(symbols.clone()),
+        );
     }
 
-    vm.run();
-    vm.fix_teleporter();
-
-    for line in vec![
-        "use teleporter",
-        "north",
-        "north",
-        "north",
-        "north",
-        "north",
-        "north",
-        "north",
-        "north",
-        "north",
-        "take orb",
-        "look orb",
-        "look",
-        "north",
-        "east",
-        "east",
-        "north",
-        "west",
-        "south",
-        "east",
-        "east",
-        "west",
-        "north",
-        "north",
-        "east",
-        "vault",
-        "take mirror",
-        "use mirror",
-    ] {
-        add_line_of_input(&mut vm, line);
+    let mut outcome: io::Result<()> = Ok(());
+    for script in &mut options.scripts {
+        script.set_symbols(symbols.clone());
+        outcome = script
+            .run(&mut vm, &mut |vm: &mut VM| {
+                print!("\n-- paused, press enter to continue --");
+                std::io::stdout().flush().unwrap();
+                io::stdin().lock().lines().next();
+                vm.get_output();
+            })
+            .map_err(io::Error::from);
+        if outcome.is_err() {
+            break;
+        }
     }
 
-    loop {
-        vm.run();
+    // A failed script skips the prompt, but its trace and transcript are
+    // still saved below.
+    if outcome.is_ok() {
+        loop {
+            let state = vm.run();
+            vm.get_output();
+            // Report output that couldn't be decoded now, not at exit.
+            if let Err(error) = vm.flush_output() {
+                outcome = Err(error);
+                break;
+            }
 
-        if vm.get_state() == State::WaitingForInput {
-            println!("{}", vm.get_output());
-            print!("> ");
+            match state {
+                // The cycle budget only bounds each run; keep going.
+                State::Paused => continue,
+                State::WaitingForInput => {}
+                _ => break,
+            }
+
+            print!("\n> ");
             std::io::stdout().flush().unwrap();
 
             if let Some(Ok(line)) = io::stdin().lock().lines().next() {
-                add_line_of_input(&mut vm, line.as_str());
+                if !run_command(&mut vm, line.as_str()) {
+                    add_line_of_input(&mut vm, line.as_str());
+                }
+            } else {
+                break;
             }
             println!();
-
-            vm.run();
-        } else {
-            break;
         }
     }
 
@@ -156,7 +257,25 @@ fn main() -> std::io::Result<()> {
         vm.get_state(),
         vm.get_cycles()
     );
-    println!("OUTPUT: {}", vm.get_output());
+
+    if let Some(memo) = vm.get_memoizer() {
+        print!("{}", memo.report(&symbols));
+    }
+
+    let finished = finish(&mut vm, &options);
+    outcome.and(finished)
+}
+
+fn finish(vm: &mut VM, options: &Options) -> io::Result<()> {
+    vm.flush_output()?;
+    if let Some(tracer) = vm.take_tracer() {
+        tracer.finish()?;
+    }
+    if let (Some(path), Some(mut transcript)) = (&options.record_path, vm.take_transcript()) {
+        transcript.finish(vm.get_cycles());
+        transcript.save(path)?;
+        println!("Saved transcript to `{}`.", path);
+    }
 
     Ok(())
 }
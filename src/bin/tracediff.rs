@@ -0,0 +1,143 @@
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use synacor_challenge::trace::TraceEntry;
+use synacor_challenge::trace_diff::{address_ranges, diff, Divergence};
+
+const WINDOW: usize = 100_000;
+const MAX_REGIONS: usize = 20;
+
+fn load(path: &str) -> io::Result<Vec<TraceEntry>> {
+    let file = std::fs::File::open(path)?;
+    let mut entries = vec![];
+
+    for (number, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = TraceEntry::from_json(&line).map_err(|message| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, number + 1, message),
+            )
+        })?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+fn describe(label: &str, entry: Option<&TraceEntry>) {
+    match entry {
+        Some(entry) => {
+            println!("  {}: {}", label, entry.to_text().trim_start());
+            println!(
+                "  {}  registers {:?}  stack depth {}",
+                " ".repeat(label.len()),
+                entry.registers,
+                entry.stack_depth
+            );
+        }
+        None => println!("  {}: <end of trace>", label),
+    }
+}
+
+fn summarize(entries: &[TraceEntry]) -> String {
+    match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => {
+            let ranges: Vec<String> = address_ranges(entries)
+                .iter()
+                .map(|range| format!("{}..{}", range.start, range.end))
+                .collect();
+
+            format!(
+                "{} instructions, cycles {}..={}, addresses {}",
+                entries.len(),
+                first.cycle,
+                last.cycle,
+                ranges.join(" ")
+            )
+        }
+        _ => "nothing".to_owned(),
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut paths: Vec<String> = vec![];
+    let mut window = WINDOW;
+    let mut max_regions = MAX_REGIONS;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--window" | "--max-regions" => {
+                let value = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{} needs a number", arg),
+                        )
+                    })?;
+
+                if arg == "--window" {
+                    window = value;
+                } else {
+                    max_regions = value;
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: tracediff <left.json> <right.json> [--window n] [--max-regions n]",
+        ));
+    }
+
+    let left = load(&paths[0])?;
+    let right = load(&paths[1])?;
+    let result = diff(&left, &right, window);
+
+    match result.first_divergence {
+        None => {
+            println!("traces are identical ({} instructions)", result.matched);
+            return Ok(());
+        }
+        Some((i, j)) => {
+            println!("first divergence at left entry {}, right entry {}:", i, j);
+            describe("left", left.get(i));
+            describe("right", right.get(j));
+        }
+    }
+
+    println!();
+    println!(
+        "{} matching instructions, {} divergent regions",
+        result.matched,
+        result.regions.len()
+    );
+
+    for region in result.regions.iter().take(max_regions) {
+        if region.kind == Divergence::State {
+            println!(
+                "- same instructions, different state: left {:?}, right {:?}",
+                region.left, region.right
+            );
+            continue;
+        }
+        println!("- left:  {}", summarize(&left[region.left.clone()]));
+        println!("  right: {}", summarize(&right[region.right.clone()]));
+    }
+
+    if result.regions.len() > max_regions {
+        println!("... {} more", result.regions.len() - max_regions);
+    }
+
+    Ok(())
+}
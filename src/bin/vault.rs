@@ -0,0 +1,67 @@
+use std::env;
+use std::fs;
+use std::io;
+use synacor_challenge::snapshot::Snapshot;
+use synacor_challenge::vault::{explore, parse_grid, Vault};
+use synacor_challenge::vm::VM;
+
+const USAGE: &str = "usage: vault [<grid> | --snapshot <path>] [--start <row>,<column>] \
+                     [--goal <row>,<column>] [--weight <weight>] [--target <weight>]";
+
+/// The vault in challenge.bin, with the antechamber in the south-west corner.
+const CHALLENGE_GRID: &str = "
+    * 8 - 1
+    4 * 11 *
+    + 4 - 18
+    22 - 9 *
+";
+
+fn usage() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+fn number<T: std::str::FromStr>(value: Option<String>) -> io::Result<T> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(usage)
+}
+
+fn position(value: Option<String>) -> io::Result<(usize, usize)> {
+    let value = value.ok_or_else(usage)?;
+    let (row, column) = value.split_once(',').ok_or_else(usage)?;
+    Ok((
+        number(Some(row.to_owned()))?,
+        number(Some(column.to_owned()))?,
+    ))
+}
+
+fn main() -> io::Result<()> {
+    let mut vault = Vault {
+        grid: parse_grid(CHALLENGE_GRID)?,
+        start: (3, 0),
+        goal: (0, 3),
+        start_weight: 22,
+        target: 30,
+    };
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--start" => vault.start = position(args.next())?,
+            "--goal" => vault.goal = position(args.next())?,
+            "--weight" => vault.start_weight = number(args.next())?,
+            "--target" => vault.target = number(args.next())?,
+            "--snapshot" => {
+                // Read the grid off a game saved in the antechamber.
+                let snapshot = Snapshot::load(args.next().ok_or_else(usage)?)?;
+                vault = explore(&VM::from_snapshot(snapshot))?;
+            }
+            _ if arg.starts_with("--") => return Err(usage()),
+            _ => vault.grid = parse_grid(&fs::read_to_string(&arg)?)?,
+        }
+    }
+
+    for direction in vault.solve()? {
+        println!("{}", direction.command());
+    }
+
+    Ok(())
+}
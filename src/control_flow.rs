@@ -0,0 +1,515 @@
+use crate::disassembly::Disassembly;
+use crate::vm::{Operation, Param};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Address(usize),
+    /// The destination is whatever the register holds at runtime.
+    Unresolved(usize),
+    /// Control leaves the function through `ret`.
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+    Jump,
+    JumpIfTrue,
+    JumpIfFalse,
+    Fallthrough,
+    Return,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Target,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub site: usize,
+    pub target: Target,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// Addresses of the instructions in the block, in order.
+    pub instructions: Vec<usize>,
+    pub calls: Vec<Call>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub entry: usize,
+    pub blocks: BTreeSet<usize>,
+    pub calls: Vec<Call>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ControlFlow {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub edges: Vec<Edge>,
+    pub functions: BTreeMap<usize, Function>,
+}
+
+/// Where the jump or call at `site` goes, as far as the disassembler knows.
+fn target(disassembly: &Disassembly, site: usize, param: Param) -> Target {
+    match param {
+        Param::Literal(address) => Target::Address(address as usize),
+        Param::Register(index) => match disassembly.resolved.get(&site) {
+            Some(&address) => Target::Address(address),
+            None => Target::Unresolved(index),
+        },
+    }
+}
+
+/// Splits the decoded instructions into basic blocks and groups them into
+/// functions, one per entry point and `call` target. Calls don't end a block;
+/// they show up in the call graph instead.
+pub fn analyze(disassembly: &Disassembly, entry_points: &[usize]) -> ControlFlow {
+    let instructions = &disassembly.instructions;
+    let mut leaders: BTreeSet<usize> = entry_points
+        .iter()
+        .chain(&disassembly.call_targets)
+        .chain(&disassembly.jump_targets)
+        .copied()
+        .filter(|address| instructions.contains_key(address))
+        .collect();
+
+    for (&address, instruction) in instructions {
+        let branches = match instruction.operation {
+            Operation::Call(_) => false,
+            ref operation => operation.target().is_some() || !operation.falls_through(),
+        };
+        if branches && instructions.contains_key(&(address + instruction.length)) {
+            leaders.insert(address + instruction.length);
+        }
+    }
+
+    let mut blocks: BTreeMap<usize, BasicBlock> = BTreeMap::new();
+    let mut edges: Vec<Edge> = vec![];
+
+    for &start in &leaders {
+        let mut block = BasicBlock {
+            start,
+            instructions: vec![],
+            calls: vec![],
+        };
+        let mut address = start;
+
+        while let Some(instruction) = instructions.get(&address) {
+            block.instructions.push(address);
+            let next = address + instruction.length;
+            let target = |param| target(disassembly, address, param);
+
+            let (taken, fallthrough) = match instruction.operation {
+                Operation::Call(to) => {
+                    block.calls.push(Call {
+                        site: address,
+                        target: target(to),
+                    });
+                    (None, true)
+                }
+                Operation::Jump(to) => (Some((target(to), EdgeKind::Jump)), false),
+                Operation::JumpIfTrue(_, to) => (Some((target(to), EdgeKind::JumpIfTrue)), true),
+                Operation::JumpIfFalse(_, to) => (Some((target(to), EdgeKind::JumpIfFalse)), true),
+                Operation::Return => (Some((Target::Exit, EdgeKind::Return)), false),
+                Operation::Halt => (None, false),
+                _ => (None, true),
+            };
+
+            if let Some((to, kind)) = taken {
+                edges.push(Edge {
+                    from: start,
+                    to,
+                    kind,
+                });
+            }
+
+            let ends_block = taken.is_some() || !fallthrough || leaders.contains(&next);
+            if fallthrough && ends_block && instructions.contains_key(&next) {
+                edges.push(Edge {
+                    from: start,
+                    to: Target::Address(next),
+                    kind: EdgeKind::Fallthrough,
+                });
+            }
+            if ends_block {
+                break;
+            }
+            address = next;
+        }
+
+        blocks.insert(start, block);
+    }
+
+    let mut functions: BTreeMap<usize, Function> = BTreeMap::new();
+    for &entry in entry_points.iter().chain(&disassembly.call_targets) {
+        if blocks.contains_key(&entry) {
+            functions.insert(entry, function(entry, &blocks, &edges));
+        }
+    }
+
+    ControlFlow {
+        blocks,
+        edges,
+        functions,
+    }
+}
+
+fn function(entry: usize, blocks: &BTreeMap<usize, BasicBlock>, edges: &[Edge]) -> Function {
+    let mut reached: BTreeSet<usize> = BTreeSet::new();
+    let mut pending = vec![entry];
+
+    while let Some(start) = pending.pop() {
+        if !blocks.contains_key(&start) || !reached.insert(start) {
+            continue;
+        }
+        for edge in edges.iter().filter(|edge| edge.from == start) {
+            if let Target::Address(to) = edge.to {
+                pending.push(to);
+            }
+        }
+    }
+
+    let calls = reached
+        .iter()
+        .flat_map(|start| blocks[start].calls.iter().cloned())
+        .collect();
+
+    Function {
+        entry,
+        blocks: reached,
+        calls,
+    }
+}
+
+impl ControlFlow {
+    pub fn edges_from(&self, start: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == start)
+    }
+
+    pub fn write_call_graph_dot<W: Write>(
+        &self,
+        disassembly: &Disassembly,
+        out: &mut W,
+    ) -> io::Result<()> {
+        writeln!(out, "digraph calls {{")?;
+        writeln!(out, "  node [shape=box, fontname=monospace];")?;
+
+        for &entry in self.functions.keys() {
+            let name = disassembly
+                .label(entry)
+                .unwrap_or_else(|| format!("fn_{}", entry));
+            writeln!(out, "  fn_{} [label=\"{}\"];", entry, name)?;
+        }
+
+        for function in self.functions.values() {
+            let mut seen: BTreeSet<String> = BTreeSet::new();
+
+            for call in &function.calls {
+                let callee = match call.target {
+                    Target::Address(address) => format!("fn_{}", address),
+                    Target::Unresolved(index) => {
+                        let node = format!("unresolved_{}", call.site);
+                        writeln!(
+                            out,
+                            "  {} [label=\"call #{} at {}\", style=dashed];",
+                            node, index, call.site
+                        )?;
+                        node
+                    }
+                    Target::Exit => continue,
+                };
+                if seen.insert(callee.clone()) {
+                    writeln!(out, "  fn_{} -> {};", function.entry, callee)?;
+                }
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+
+    /// Writes the CFG of every function in `entries` as a cluster of one
+    /// digraph. Blocks shared between functions appear in each of them.
+    pub fn write_cfg_dot<W: Write>(
+        &self,
+        disassembly: &Disassembly,
+        entries: &[usize],
+        out: &mut W,
+    ) -> io::Result<()> {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "  node [shape=box, fontname=monospace];")?;
+
+        for &entry in entries {
+            let function = match self.functions.get(&entry) {
+                Some(function) => function,
+                None => continue,
+            };
+            let node = |address: usize| format!("f{}_b{}", entry, address);
+
+            writeln!(out, "  subgraph cluster_{} {{", entry)?;
+            let name = disassembly
+                .label(entry)
+                .unwrap_or_else(|| format!("fn_{}", entry));
+            writeln!(out, "    label=\"{}\";", name)?;
+
+            for start in &function.blocks {
+                let label: String = self.blocks[start]
+                    .instructions
+                    .iter()
+                    .map(|address| {
+                        let operation = &disassembly.instructions[address].operation;
+                        format!(
+                            "{}: {}\\l",
+                            address,
+                            dot_escape(&disassembly.format_operation(operation))
+                        )
+                    })
+                    .collect();
+                writeln!(out, "    {} [label=\"{}\"];", node(*start), label)?;
+            }
+
+            for start in &function.blocks {
+                for edge in self.edges_from(*start) {
+                    let (to, style) = match edge.to {
+                        Target::Address(address) => (node(address), ""),
+                        Target::Unresolved(index) => {
+                            let to = format!("f{}_unresolved_{}", entry, start);
+                            writeln!(
+                                out,
+                                "    {} [label=\"#{}\", shape=ellipse, style=dashed];",
+                                to, index
+                            )?;
+                            (to, ", style=dashed")
+                        }
+                        Target::Exit => {
+                            let to = format!("f{}_exit", entry);
+                            writeln!(out, "    {} [label=\"ret\", shape=ellipse];", to)?;
+                            (to, "")
+                        }
+                    };
+                    writeln!(
+                        out,
+                        "    {} -> {} [label=\"{}\"{}];",
+                        node(*start),
+                        to,
+                        edge_name(edge.kind),
+                        style
+                    )?;
+                }
+            }
+
+            writeln!(out, "  }}")?;
+        }
+
+        writeln!(out, "}}")
+    }
+
+    pub fn write_json<W: Write>(&self, disassembly: &Disassembly, out: &mut W) -> io::Result<()> {
+        let functions: Vec<String> = self
+            .functions
+            .values()
+            .map(|function| {
+                let blocks: Vec<String> = function.blocks.iter().map(|b| b.to_string()).collect();
+                let calls: Vec<String> = function.calls.iter().map(call_json).collect();
+                format!(
+                    "{{\"entry\":{},\"blocks\":[{}],\"calls\":[{}]}}",
+                    function.entry,
+                    blocks.join(","),
+                    calls.join(",")
+                )
+            })
+            .collect();
+
+        let blocks: Vec<String> = self
+            .blocks
+            .values()
+            .map(|block| {
+                let instructions: Vec<String> = block
+                    .instructions
+                    .iter()
+                    .map(|address| {
+                        let operation = &disassembly.instructions[address].operation;
+                        format!(
+                            "{{\"address\":{},\"text\":\"{}\"}}",
+                            address,
+                            dot_escape(&disassembly.format_operation(operation))
+                        )
+                    })
+                    .collect();
+                let edges: Vec<String> = self
+                    .edges_from(block.start)
+                    .map(|edge| {
+                        format!(
+                            "{{\"kind\":\"{}\",{}}}",
+                            edge_name(edge.kind),
+                            target_json(edge.to)
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"start\":{},\"instructions\":[{}],\"edges\":[{}]}}",
+                    block.start,
+                    instructions.join(","),
+                    edges.join(",")
+                )
+            })
+            .collect();
+
+        writeln!(
+            out,
+            "{{\"functions\":[{}],\"blocks\":[{}]}}",
+            functions.join(","),
+            blocks.join(",")
+        )
+    }
+}
+
+fn edge_name(kind: EdgeKind) -> &'static str {
+    match kind {
+        EdgeKind::Jump => "jmp",
+        EdgeKind::JumpIfTrue => "jt",
+        EdgeKind::JumpIfFalse => "jf",
+        EdgeKind::Fallthrough => "fallthrough",
+        EdgeKind::Return => "ret",
+    }
+}
+
+fn target_json(target: Target) -> String {
+    match target {
+        Target::Address(address) => format!("\"to\":{}", address),
+        Target::Unresolved(index) => format!("\"unresolved\":{}", index),
+        Target::Exit => "\"exit\":true".to_owned(),
+    }
+}
+
+fn call_json(call: &Call) -> String {
+    format!("{{\"site\":{},{}}}", call.site, target_json(call.target))
+}
+
+/// DOT and JSON strings both escape quotes and backslashes the same way.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::disassembly::disassemble;
+
+    fn control_flow(source: &str) -> (Disassembly, ControlFlow) {
+        let words = assemble(source).unwrap().words;
+        let disassembly = disassemble(&words, &[0]);
+        let control_flow = analyze(&disassembly, &[0]);
+        (disassembly, control_flow)
+    }
+
+    #[test]
+    fn blocks_and_edges_test() {
+        let (_, flow) = control_flow(
+            "
+            call(function)
+            call(#3)
+            halt
+            function: jf(#0, done)
+            add(#0, #0, 1)
+            jmp(#1)
+            done: ret
+            ",
+        );
+
+        let starts: Vec<usize> = flow.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 5, 8, 14]);
+        assert_eq!(flow.blocks[&0].instructions, vec![0, 2, 4]);
+        assert_eq!(
+            flow.edges,
+            vec![
+                Edge {
+                    from: 5,
+                    to: Target::Address(14),
+                    kind: EdgeKind::JumpIfFalse,
+                },
+                Edge {
+                    from: 5,
+                    to: Target::Address(8),
+                    kind: EdgeKind::Fallthrough,
+                },
+                Edge {
+                    from: 8,
+                    to: Target::Unresolved(1),
+                    kind: EdgeKind::Jump,
+                },
+                Edge {
+                    from: 14,
+                    to: Target::Exit,
+                    kind: EdgeKind::Return,
+                },
+            ]
+        );
+
+        let functions: Vec<usize> = flow.functions.keys().copied().collect();
+        assert_eq!(functions, vec![0, 5]);
+        assert_eq!(
+            flow.functions[&0].calls,
+            vec![
+                Call {
+                    site: 0,
+                    target: Target::Address(5),
+                },
+                Call {
+                    site: 2,
+                    target: Target::Unresolved(3),
+                },
+            ]
+        );
+        let blocks: Vec<usize> = flow.functions[&5].blocks.iter().copied().collect();
+        assert_eq!(blocks, vec![5, 8, 14]);
+    }
+
+    #[test]
+    fn resolved_call_test() {
+        let (_, flow) = control_flow("setr(#2, function)\ncall(#2)\nhalt\nfunction: ret");
+
+        let functions: Vec<usize> = flow.functions.keys().copied().collect();
+        assert_eq!(functions, vec![0, 6]);
+        assert_eq!(
+            flow.functions[&0].calls,
+            vec![Call {
+                site: 3,
+                target: Target::Address(6),
+            }]
+        );
+    }
+
+    #[test]
+    fn export_test() {
+        let (disassembly, flow) = control_flow("call(f)\nhalt\nf: out(\"a\\\"\")\nret");
+
+        let mut dot = vec![];
+        flow.write_call_graph_dot(&disassembly, &mut dot).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph calls {\n  node [shape=box, fontname=monospace];\n  \
+             fn_0 [label=\"fn_0\"];\n  fn_3 [label=\"fn_3\"];\n  fn_0 -> fn_3;\n}\n"
+        );
+
+        let mut json = vec![];
+        flow.write_json(&disassembly, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with(
+            "{\"functions\":[{\"entry\":0,\"blocks\":[0],\"calls\":[{\"site\":0,\"to\":3}]}"
+        ));
+        assert!(json.contains(
+            "{\"start\":3,\"instructions\":[{\"address\":3,\"text\":\"out(\\\"a\\\")\"},\
+             {\"address\":5,\"text\":\"out(\\\"\\\\\\\"\\\")\"},{\"address\":7,\"text\":\"ret\"}],\
+             \"edges\":[{\"kind\":\"ret\",\"exit\":true}]}"
+        ));
+    }
+}
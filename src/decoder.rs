@@ -0,0 +1,181 @@
+use crate::vm::{Operation, Param, Word, MOD, REGISTERS};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    InvalidOpcode { address: usize, opcode: Word },
+    InvalidRegister { address: usize, word: Word },
+    Truncated { address: usize, end: usize },
+}
+
+/// Decodes the instruction at `address`, returning it with its length in
+/// words. Works on any slice of words, so no `VM` is needed.
+pub fn decode(memory: &[Word], address: usize) -> Result<(Operation, usize), DecodeError> {
+    let mut cursor = Cursor {
+        memory,
+        start: address,
+        address,
+    };
+    let opcode = cursor.read_word()?;
+
+    let operation = match opcode {
+        0 => Operation::Halt,
+        1 => Operation::SetRegister(cursor.read_param()?, cursor.read_param()?),
+        2 => Operation::Push(cursor.read_param()?),
+        3 => Operation::Pop(cursor.read_param()?),
+        4 => Operation::Equal(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        5 => Operation::GreaterThan(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        6 => Operation::Jump(cursor.read_param()?),
+        7 => Operation::JumpIfTrue(cursor.read_param()?, cursor.read_param()?),
+        8 => Operation::JumpIfFalse(cursor.read_param()?, cursor.read_param()?),
+        9 => Operation::Add(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        10 => Operation::Mult(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        11 => Operation::Mod(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        12 => Operation::And(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        13 => Operation::Or(
+            cursor.read_param()?,
+            cursor.read_param()?,
+            cursor.read_param()?,
+        ),
+        14 => Operation::Not(cursor.read_param()?, cursor.read_param()?),
+        15 => Operation::ReadMemory(cursor.read_param()?, cursor.read_param()?),
+        16 => Operation::WriteMemory(cursor.read_param()?, cursor.read_param()?),
+        17 => Operation::Call(cursor.read_param()?),
+        18 => Operation::Return,
+        19 => Operation::Out(cursor.read_param()?),
+        20 => Operation::In(cursor.read_param()?),
+        21 => Operation::NoOp,
+        opcode => return Err(DecodeError::InvalidOpcode { address, opcode }),
+    };
+
+    Ok((operation, cursor.address - address))
+}
+
+struct Cursor<'a> {
+    memory: &'a [Word],
+    start: usize,
+    address: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn read_word(&mut self) -> Result<Word, DecodeError> {
+        let word = *self
+            .memory
+            .get(self.address)
+            .ok_or(DecodeError::Truncated {
+                address: self.start,
+                end: self.address,
+            })?;
+        self.address += 1;
+
+        Ok(word)
+    }
+
+    fn read_param(&mut self) -> Result<Param, DecodeError> {
+        let word = self.read_word()?;
+
+        if word < MOD {
+            Ok(Param::Literal(word))
+        } else if word < MOD + REGISTERS as Word {
+            Ok(Param::Register((word - MOD) as usize))
+        } else {
+            Err(DecodeError::InvalidRegister {
+                address: self.start,
+                word,
+            })
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidOpcode { address, opcode } => {
+                write!(f, "unknown opcode: {} at {}", opcode, address)
+            }
+            DecodeError::InvalidRegister { address, word } => {
+                write!(f, "invalid register {} at {}", word, address)
+            }
+            DecodeError::Truncated { address, end } => write!(
+                f,
+                "instruction at {} runs past the end of memory at {}",
+                address, end
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_test() {
+        let memory = [9, 32768, 32769, 4, 19, 32768, 18];
+
+        assert_eq!(
+            decode(&memory, 0),
+            Ok((
+                Operation::Add(Param::Register(0), Param::Register(1), Param::Literal(4)),
+                4
+            ))
+        );
+        assert_eq!(
+            decode(&memory, 4),
+            Ok((Operation::Out(Param::Register(0)), 2))
+        );
+        assert_eq!(decode(&memory, 6), Ok((Operation::Return, 1)));
+    }
+
+    #[test]
+    fn decode_error_test() {
+        assert_eq!(
+            decode(&[22], 0),
+            Err(DecodeError::InvalidOpcode {
+                address: 0,
+                opcode: 22
+            })
+        );
+        assert_eq!(
+            decode(&[21, 1, 32776, 0], 1),
+            Err(DecodeError::InvalidRegister {
+                address: 1,
+                word: 32776
+            })
+        );
+        assert_eq!(
+            decode(&[21, 9, 32768], 1),
+            Err(DecodeError::Truncated { address: 1, end: 3 })
+        );
+        assert_eq!(
+            decode(&[21], 1),
+            Err(DecodeError::Truncated { address: 1, end: 1 })
+        );
+    }
+}
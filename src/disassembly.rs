@@ -0,0 +1,529 @@
+use crate::decoder::decode;
+use crate::symbols::{DataType, Symbols};
+use crate::vm::{Operation, Param, Word, MOD, REGISTERS};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+const WORDS_PER_LINE: usize = 8;
+const MIN_STRING_LENGTH: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub operation: Operation,
+    pub length: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Region {
+    Code(Range<usize>),
+    Data(Range<usize>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Disassembly {
+    pub memory: Vec<Word>,
+    pub instructions: BTreeMap<usize, Instruction>,
+    pub jump_targets: BTreeSet<usize>,
+    pub call_targets: BTreeSet<usize>,
+    /// Targets of jumps and calls through a register that a `setr` of a
+    /// literal sets just before, by the address of the jump or call.
+    pub resolved: BTreeMap<usize, usize>,
+    /// Jumps and calls through a register whose target isn't known, so code
+    /// only reached from them needs another entry point.
+    pub unresolved: BTreeSet<usize>,
+    pub symbols: Symbols,
+}
+
+/// Decodes everything reachable from `entry_points` by following fallthrough
+/// and `jmp`/`jt`/`jf`/`call` targets. A target held in a register is only
+/// followed when a `setr` of a literal sets it just before; other such jumps
+/// are listed in `unresolved`, and code only reached from them is left as data.
+pub fn disassemble(memory: &[Word], entry_points: &[usize]) -> Disassembly {
+    let mut instructions: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut jump_targets: BTreeSet<usize> = BTreeSet::new();
+    let mut call_targets: BTreeSet<usize> = BTreeSet::new();
+    let mut resolved: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut unresolved: BTreeSet<usize> = BTreeSet::new();
+    let mut pending: Vec<usize> = entry_points.to_vec();
+
+    while let Some(mut address) = pending.pop() {
+        let mut constant: Option<(usize, Word)> = None;
+
+        while address < memory.len() && !instructions.contains_key(&address) {
+            let (operation, length) = match decode(memory, address) {
+                Ok(decoded) => decoded,
+                Err(_) => break,
+            };
+
+            let target = match operation.target() {
+                Some(Param::Literal(target)) => Some(target),
+                Some(Param::Register(index)) => match constant {
+                    Some((register, value)) if register == index => {
+                        resolved.insert(address, value as usize);
+                        Some(value)
+                    }
+                    _ => {
+                        unresolved.insert(address);
+                        None
+                    }
+                },
+                None => None,
+            };
+            constant = match operation {
+                Operation::SetRegister(Param::Register(index), Param::Literal(value)) => {
+                    Some((index, value))
+                }
+                _ => None,
+            };
+
+            if let Some(target) = target {
+                let target = target as usize;
+
+                if target < memory.len() {
+                    match operation {
+                        Operation::Call(_) => call_targets.insert(target),
+                        _ => jump_targets.insert(target),
+                    };
+                    pending.push(target);
+                }
+            }
+
+            let falls_through = operation.falls_through();
+            instructions.insert(address, Instruction { operation, length });
+
+            if !falls_through {
+                break;
+            }
+            address += length;
+        }
+    }
+
+    Disassembly {
+        memory: memory.to_vec(),
+        instructions,
+        jump_targets,
+        call_targets,
+        resolved,
+        unresolved,
+        symbols: Symbols::new(),
+    }
+}
+
+impl Disassembly {
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn label(&self, address: usize) -> Option<String> {
+        if let Some(name) = self.symbols.get_name(address) {
+            Some(name.to_owned())
+        } else if self.call_targets.contains(&address) {
+            Some(format!("fn_{}", address))
+        } else if self.jump_targets.contains(&address) {
+            Some(format!("label_{}", address))
+        } else {
+            None
+        }
+    }
+
+    /// Splits memory into code and data, walking code instruction by
+    /// instruction so overlapping decodes don't produce overlapping regions.
+    /// An instruction with a label or comment inside it is left as data, so
+    /// the listing can still place them.
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions: Vec<Region> = vec![];
+        let mut address = 0;
+
+        while address < self.memory.len() {
+            let (is_code, next) = match self.instructions.get(&address) {
+                Some(instruction)
+                    if !(address + 1..address + instruction.length)
+                        .any(|inside| self.is_marked(inside)) =>
+                {
+                    (true, address + instruction.length)
+                }
+                _ => (false, address + 1),
+            };
+
+            match regions.last_mut() {
+                Some(Region::Code(range)) if is_code && range.end == address => range.end = next,
+                Some(Region::Data(range)) if !is_code && range.end == address => range.end = next,
+                _ if is_code => regions.push(Region::Code(address..next)),
+                _ => regions.push(Region::Data(address..next)),
+            }
+
+            address = next;
+        }
+
+        regions
+    }
+
+    pub fn format_operation(&self, operation: &Operation) -> String {
+        let target = operation.target();
+        let params: Vec<String> = operation
+            .params()
+            .into_iter()
+            .map(|param| match param {
+                Param::Register(index) => format!("#{}", index),
+                Param::Literal(value) if Some(param) == target => self
+                    .label(value as usize)
+                    .unwrap_or_else(|| value.to_string()),
+                Param::Literal(value) => match operation {
+                    Operation::Out(_) if is_printable(value) => quote(&[value]),
+                    _ => value.to_string(),
+                },
+            })
+            .collect();
+
+        if params.is_empty() {
+            operation.mnemonic().to_owned()
+        } else {
+            format!("{}({})", operation.mnemonic(), params.join(", "))
+        }
+    }
+
+    pub fn write_listing<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for region in self.regions() {
+            match region {
+                Region::Code(range) => {
+                    let mut address = range.start;
+
+                    while address < range.end {
+                        let instruction = &self.instructions[&address];
+                        self.write_annotations(out, address)?;
+
+                        let text = self.printed_text(address, range.end);
+                        if text.len() > 1 {
+                            writeln!(out, "{}: out({})", address, quote(&text))?;
+                            address += text.len() * instruction.length;
+                            continue;
+                        }
+
+                        writeln!(
+                            out,
+                            "{}: {}",
+                            address,
+                            self.format_operation(&instruction.operation)
+                        )?;
+                        address += instruction.length;
+                    }
+                }
+                Region::Data(range) => {
+                    let overlapping: Vec<String> = self
+                        .instructions
+                        .range(range.clone())
+                        .map(|(address, _)| address.to_string())
+                        .collect();
+                    if overlapping.is_empty() {
+                        writeln!(out, "; data {}..{}", range.start, range.end)?;
+                    } else {
+                        writeln!(
+                            out,
+                            "; data {}..{}, overlapping code decoded at {}",
+                            range.start,
+                            range.end,
+                            overlapping.join(", ")
+                        )?;
+                    }
+                    self.write_data(out, range)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects the characters of consecutive printable literal `out`s starting
+    /// at `address`, stopping at `end` or at the next label or comment.
+    fn printed_text(&self, mut address: usize, end: usize) -> Vec<Word> {
+        let mut text = vec![];
+
+        while address < end && (text.is_empty() || !self.is_marked(address)) {
+            match self.instructions.get(&address) {
+                Some(Instruction {
+                    operation: Operation::Out(Param::Literal(value)),
+                    length,
+                }) if is_printable(*value) => {
+                    text.push(*value);
+                    address += length;
+                }
+                _ => break,
+            }
+        }
+
+        text
+    }
+
+    fn is_marked(&self, address: usize) -> bool {
+        self.label(address).is_some() || self.symbols.is_annotated(address)
+    }
+
+    fn write_annotations<W: Write>(&self, out: &mut W, address: usize) -> io::Result<()> {
+        if let Some(label) = self.label(address) {
+            writeln!(out, "{}:", label)?;
+        }
+        if let Some(comment) = self.symbols.get_comment(address) {
+            writeln!(out, "; {}", comment)?;
+        }
+        Ok(())
+    }
+
+    fn write_data<W: Write>(&self, out: &mut W, range: Range<usize>) -> io::Result<()> {
+        let mut address = range.start;
+
+        while address < range.end {
+            self.write_annotations(out, address)?;
+
+            // Lines never run past the next annotated address, so its label
+            // and comment land where they belong.
+            let end = (address + 1..range.end)
+                .find(|&next| self.is_marked(next))
+                .unwrap_or(range.end);
+            let words = &self.memory[address..end];
+
+            let string = match self.symbols.get_data_type(address) {
+                Some(DataType::Words) => None,
+                Some(DataType::Text) => string_at(words, 1),
+                _ => string_at(words, MIN_STRING_LENGTH),
+            };
+
+            let (line, length) = match string {
+                Some(StringData::Prefixed(length)) => (
+                    format!("data {}, {}", length, quote(&words[1..1 + length])),
+                    length + 1,
+                ),
+                Some(StringData::Plain(length)) => {
+                    (format!("data {}", quote(&words[..length])), length)
+                }
+                None => {
+                    let length = words
+                        .iter()
+                        .take(WORDS_PER_LINE)
+                        .enumerate()
+                        .take_while(|&(index, _)| {
+                            index == 0 || string_at(&words[index..], MIN_STRING_LENGTH).is_none()
+                        })
+                        .count();
+                    let values: Vec<String> = words[..length]
+                        .iter()
+                        .map(|&word| match word.checked_sub(MOD) {
+                            Some(index) if (index as usize) < REGISTERS => format!("#{}", index),
+                            _ => word.to_string(),
+                        })
+                        .collect();
+                    (format!("data {}", values.join(", ")), length)
+                }
+            };
+
+            writeln!(out, "{}: {}", address, line)?;
+            address += length;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_printable(word: Word) -> bool {
+    (32..127).contains(&word) || word == b'\n' as Word
+}
+
+enum StringData {
+    Prefixed(usize),
+    Plain(usize),
+}
+
+/// Recognizes the game's length-prefixed strings, falling back to a plain run
+/// of at least `min_length` printable words.
+fn string_at(words: &[Word], min_length: usize) -> Option<StringData> {
+    let printable = |words: &[Word]| words.iter().take_while(|&&word| is_printable(word)).count();
+
+    if let Some((&length, rest)) = words.split_first() {
+        let length = length as usize;
+        if length > 0 && length <= rest.len() && printable(&rest[..length]) == length {
+            return Some(StringData::Prefixed(length));
+        }
+    }
+
+    match printable(words) {
+        length if length >= min_length => Some(StringData::Plain(length)),
+        _ => None,
+    }
+}
+
+/// Quotes printable words as an assembler string literal.
+fn quote(words: &[Word]) -> String {
+    let text: String = words
+        .iter()
+        .map(|&word| match word as u8 as char {
+            '\n' => "\\n".to_owned(),
+            '"' => "\\\"".to_owned(),
+            '\\' => "\\\\".to_owned(),
+            c => c.to_string(),
+        })
+        .collect();
+
+    format!("\"{}\"", text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn reachability_test() {
+        let source = "
+            call(function)
+            jmp(end)
+            data 9999, 'h', 'e', 'l', 'l', 'o'
+            function: jt(#0, skip)
+            noop
+            skip: ret
+            end: halt
+            data 1, 2
+        ";
+        let words = assemble(source).unwrap().words;
+        let disassembly = disassemble(&words, &[0]);
+
+        assert_eq!(
+            disassembly.regions(),
+            vec![
+                Region::Code(0..4),
+                Region::Data(4..10),
+                Region::Code(10..16),
+                Region::Data(16..18),
+            ]
+        );
+        assert_eq!(disassembly.label(10), Some("fn_10".to_owned()));
+        assert_eq!(disassembly.label(14), Some("label_14".to_owned()));
+        assert_eq!(disassembly.label(11), None);
+    }
+
+    #[test]
+    fn listing_test() {
+        let words = assemble("jmp(end)\ndata 7, \"text\"\nend: halt")
+            .unwrap()
+            .words;
+        let mut listing = vec![];
+        disassemble(&words, &[0])
+            .write_listing(&mut listing)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            "0: jmp(label_7)\n; data 2..7\n2: data 7\n3: data \"text\"\nlabel_7:\n7: halt\n"
+        );
+    }
+
+    #[test]
+    fn string_test() {
+        let source = "
+            out(\"Hi\\n\")
+            out(#2)
+            out('!')
+            loop: out('a')
+            out('b')
+            out(200)
+            jt(#0, loop)
+            halt
+            data 3, \"one\", 5, \"t\\\"wo\\n\", #1, 2
+        ";
+        let words = assemble(source).unwrap().words;
+        let mut listing = vec![];
+        disassemble(&words, &[0])
+            .write_listing(&mut listing)
+            .unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+
+        assert_eq!(
+            listing,
+            "0: out(\"Hi\\n\")\n6: out(#2)\n8: out(\"!\")\nlabel_10:\n10: out(\"ab\")\n\
+             14: out(200)\n16: jit(#0, label_10)\n19: halt\n; data 20..32\n20: data 3, \"one\"\n\
+             24: data 5, \"t\\\"wo\\n\"\n30: data #1, 2\n"
+        );
+        assert_eq!(assemble(&listing).unwrap().words, words);
+    }
+
+    #[test]
+    fn unprintable_out_test() {
+        let words = vec![19, 5, 19, 9, 19, 0, 19, 32767, 0];
+        let mut listing = vec![];
+        disassemble(&words, &[0])
+            .write_listing(&mut listing)
+            .unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+
+        assert_eq!(
+            listing,
+            "0: out(5)\n2: out(9)\n4: out(0)\n6: out(32767)\n8: halt\n"
+        );
+        assert_eq!(assemble(&listing).unwrap().words, words);
+    }
+
+    #[test]
+    fn symbols_test() {
+        let words = assemble("call(6)\nhalt\ndata 1, 2, 3\nret").unwrap().words;
+        let symbols = Symbols::parse(
+            "function 6 helper\ncomment 6 does nothing\nlabel 4 table\ntype 3 words",
+        )
+        .unwrap();
+        let mut disassembly = disassemble(&words, &[0]);
+        disassembly.set_symbols(symbols);
+        let mut listing = vec![];
+        disassembly.write_listing(&mut listing).unwrap();
+
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            "0: call(helper)\n2: halt\n; data 3..6\n3: data 1\ntable:\n4: data 2, 3\n\
+             helper:\n; does nothing\n6: ret\n"
+        );
+    }
+
+    #[test]
+    fn indirect_test() {
+        let source = "
+            setr(#0, function)
+            call(#0)
+            jmp(#1)
+            function: ret
+        ";
+        let words = assemble(source).unwrap().words;
+        let disassembly = disassemble(&words, &[0]);
+
+        assert_eq!(disassembly.regions(), vec![Region::Code(0..8)]);
+        assert_eq!(disassembly.label(7), Some("fn_7".to_owned()));
+        assert_eq!(disassembly.resolved, vec![(3, 7)].into_iter().collect());
+        assert_eq!(disassembly.unresolved, vec![5].into_iter().collect());
+    }
+
+    #[test]
+    fn overlap_test() {
+        // The jump lands on the 21 inside `out(21)`, which decodes as `noop`.
+        let words = assemble("jit(#0, 4)\nout(21)\nhalt").unwrap().words;
+        let disassembly = disassemble(&words, &[0]);
+        let mut listing = vec![];
+        disassembly.write_listing(&mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+
+        assert_eq!(
+            disassembly.regions(),
+            vec![Region::Code(0..3), Region::Data(3..4), Region::Code(4..6)]
+        );
+        assert_eq!(
+            listing,
+            "0: jit(#0, label_4)\n; data 3..4, overlapping code decoded at 3\n3: data 19\n\
+             label_4:\n4: noop\n5: halt\n"
+        );
+        assert_eq!(assemble(&listing).unwrap().words, words);
+    }
+
+    #[test]
+    fn bad_entry_test() {
+        let disassembly = disassemble(&[22, 9, 32768], &[0, 1, 50]);
+
+        assert!(disassembly.instructions.is_empty());
+        assert_eq!(disassembly.regions(), vec![Region::Data(0..3)]);
+    }
+}
@@ -1 +1,19 @@
+pub mod ackermann;
+pub mod assembler;
+pub mod control_flow;
+pub mod decoder;
+pub mod disassembly;
+pub mod memo;
+pub mod patch;
+pub mod program;
+pub mod script;
+pub mod signature;
+pub mod snapshot;
+pub mod streams;
+pub mod symbols;
+pub mod trace;
+pub mod trace_diff;
+pub mod transcript;
+pub mod undo;
+pub mod vault;
 pub mod vm;
@@ -0,0 +1,365 @@
+use crate::symbols::Symbols;
+use crate::vm::{Operation, Param, Word, REGISTERS};
+use std::collections::{BTreeMap, HashMap};
+
+/// One bit per register.
+type Mask = u8;
+
+/// What a pure call did: the registers it wrote and their final values.
+#[derive(Clone, Debug)]
+struct Outcome {
+    written: Mask,
+    values: Vec<Word>,
+    cycles: u64,
+}
+
+/// A call being watched. `read` holds the registers read before the call
+/// wrote them, which are its inputs.
+#[derive(Debug)]
+struct Frame {
+    target: usize,
+    base: usize,
+    cycle: u64,
+    entry: Vec<Word>,
+    read: Mask,
+    written: Mask,
+    pure: bool,
+    code: (usize, usize),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    pub hits: u64,
+    /// Calls that touched memory, did I/O, ran a hook or left their frame.
+    pub impure: u64,
+    pub cycles_saved: u64,
+    pub entries: usize,
+}
+
+/// Caches the register results of subroutines that turned out to be pure,
+/// keyed by the registers they read. A call is pure when it doesn't read or
+/// write memory, do I/O or touch the stack below its return address; the
+/// cache of a function is dropped when its code is written to.
+#[derive(Debug, Default)]
+pub struct Memoizer {
+    frames: Vec<Frame>,
+    cache: HashMap<usize, BTreeMap<Mask, HashMap<Vec<Word>, Outcome>>>,
+    code: HashMap<usize, (usize, usize)>,
+    stats: BTreeMap<usize, FunctionStats>,
+}
+
+impl Memoizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_stats(&self) -> &BTreeMap<usize, FunctionStats> {
+        &self.stats
+    }
+
+    pub fn cycles_saved(&self) -> u64 {
+        self.stats.values().map(|stats| stats.cycles_saved).sum()
+    }
+
+    /// Lists the functions with cached results, the most cycles saved first.
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut functions: Vec<_> = self
+            .stats
+            .iter()
+            .filter(|(_, stats)| stats.entries > 0)
+            .collect();
+        functions.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.cycles_saved));
+
+        let mut text = format!(
+            "{} functions memoized, {} cycles saved\n",
+            functions.len(),
+            self.cycles_saved()
+        );
+        for (&address, stats) in functions {
+            text.push_str(&format!(
+                "{}: {} calls, {} hits, {} impure, {} results, {} cycles saved\n",
+                symbols.describe(address),
+                stats.calls,
+                stats.hits,
+                stats.impure,
+                stats.entries,
+                stats.cycles_saved
+            ));
+        }
+
+        text
+    }
+
+    /// Forgets everything, for when memory is replaced wholesale.
+    pub(crate) fn reset(&mut self) {
+        self.frames.clear();
+        self.cache.clear();
+        self.code.clear();
+    }
+
+    /// Stops watching the calls in progress, e.g. after stepping backwards.
+    pub(crate) fn abandon(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Counts a call, returning the registers to write if its result is
+    /// cached.
+    pub(crate) fn lookup(
+        &mut self,
+        target: usize,
+        registers: &[Word],
+    ) -> Option<Vec<(usize, Word)>> {
+        let stats = self.stats.entry(target).or_default();
+        stats.calls += 1;
+
+        let (read, outcome) = self
+            .cache
+            .get(&target)?
+            .iter()
+            .find_map(|(&read, outcomes)| Some((read, outcomes.get(&inputs(read, registers))?)))?;
+        stats.hits += 1;
+        stats.cycles_saved += outcome.cycles - 1;
+        let outcome = outcome.clone();
+
+        let code = self.code.get(&target).copied();
+        if let Some(parent) = self.frames.last_mut() {
+            parent.read |= read & !parent.written;
+            parent.written |= outcome.written;
+            if let Some(code) = code {
+                parent.code = span(parent.code, code);
+            }
+        }
+
+        let written = outcome.written;
+        let indices = (0..REGISTERS).filter(|index| written & (1 << index) != 0);
+        Some(indices.zip(outcome.values).collect())
+    }
+
+    /// Starts watching a call whose return address was just pushed.
+    pub(crate) fn enter(&mut self, target: usize, registers: &[Word], base: usize, cycle: u64) {
+        self.frames.push(Frame {
+            target,
+            base,
+            cycle,
+            entry: registers.to_vec(),
+            read: 0,
+            written: 0,
+            pure: true,
+            code: (target, target),
+        });
+    }
+
+    /// Whether a `ret` with this stack depth returns from the innermost call.
+    pub(crate) fn is_return(&self, depth: usize) -> bool {
+        self.frames.last().is_some_and(|frame| frame.base == depth)
+    }
+
+    pub(crate) fn leave(&mut self, registers: &[Word], cycle: u64) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let stats = self.stats.entry(frame.target).or_default();
+
+        if frame.pure {
+            let outcome = Outcome {
+                written: frame.written,
+                values: registers
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| frame.written & (1 << index) != 0)
+                    .map(|(_, &value)| value)
+                    .collect(),
+                cycles: cycle - frame.cycle + 1,
+            };
+            let outcomes = self.cache.entry(frame.target).or_default();
+            let results = outcomes.entry(frame.read).or_default();
+            results.insert(inputs(frame.read, &frame.entry), outcome);
+            stats.entries = outcomes.values().map(HashMap::len).sum();
+
+            let code = self.code.entry(frame.target).or_insert(frame.code);
+            *code = span(*code, frame.code);
+        } else {
+            stats.impure += 1;
+        }
+
+        if let Some(parent) = self.frames.last_mut() {
+            parent.read |= frame.read & !parent.written;
+            parent.written |= frame.written;
+            parent.pure &= frame.pure;
+            parent.code = span(parent.code, frame.code);
+        }
+    }
+
+    /// Tracks the registers an instruction reads and writes, and marks every
+    /// call in progress impure if it touches memory or does I/O.
+    pub(crate) fn observe(&mut self, ip: usize, operation: &Operation) {
+        if self.frames.is_empty() {
+            return;
+        }
+
+        if matches!(
+            operation,
+            Operation::ReadMemory(..)
+                | Operation::WriteMemory(..)
+                | Operation::In(_)
+                | Operation::Out(_)
+        ) {
+            self.taint();
+        }
+
+        let frame = self.frames.last_mut().unwrap();
+        frame.code = span(frame.code, (ip, ip));
+        let params = operation.params();
+        let (destination, sources) = match operation.destination() {
+            Some(_) => (params.first().copied(), &params[1..]),
+            None => (None, &params[..]),
+        };
+
+        for source in sources {
+            if let Param::Register(index) = *source {
+                frame.read |= (1 << index) & !frame.written;
+            }
+        }
+        if let Some(Param::Register(index)) = destination {
+            frame.written |= 1 << index;
+        }
+    }
+
+    /// Marks every call in progress impure.
+    pub(crate) fn taint(&mut self) {
+        for frame in &mut self.frames {
+            frame.pure = false;
+        }
+    }
+
+    /// Drops the calls whose return address was popped some other way.
+    pub(crate) fn stack_shrunk(&mut self, depth: usize) {
+        while self.frames.last().is_some_and(|frame| frame.base > depth) {
+            let frame = self.frames.pop().unwrap();
+            self.stats.entry(frame.target).or_default().impure += 1;
+            self.taint();
+        }
+    }
+
+    pub(crate) fn memory_written(&mut self, address: usize) {
+        let stale: Vec<usize> = self
+            .code
+            .iter()
+            .filter(|(_, &(start, end))| (start..=end).contains(&address))
+            .map(|(&target, _)| target)
+            .collect();
+
+        for target in stale {
+            self.cache.remove(&target);
+            self.code.remove(&target);
+            if let Some(stats) = self.stats.get_mut(&target) {
+                stats.entries = 0;
+            }
+        }
+    }
+}
+
+fn inputs(read: Mask, registers: &[Word]) -> Vec<Word> {
+    (0..REGISTERS)
+        .filter(|index| read & (1 << index) != 0)
+        .map(|index| registers[index])
+        .collect()
+}
+
+fn span(a: (usize, usize), b: (usize, usize)) -> (usize, usize) {
+    (a.0.min(b.0), a.1.max(b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::symbols::Symbols;
+    use crate::vm::{State, VM};
+
+    /// Prints the triangle numbers of 20, 20 and 25 from a recursive
+    /// function, then reads a word from memory through an impure one.
+    fn program() -> Vec<u16> {
+        let source = "
+            setr(#0, 20)
+            call(triangle)
+            out(#1)
+            call(triangle)
+            out(#1)
+            setr(#0, 25)
+            call(triangle)
+            out(#1)
+            call(peek)
+            call(peek)
+            halt
+
+            triangle: jt(#0, recurse)
+            setr(#1, 0)
+            ret
+            recurse: push(#0)
+            add(#0, #0, 32767)
+            call(triangle)
+            pop(#0)
+            add(#1, #1, #0)
+            ret
+
+            peek: rmem(#2, 0)
+            ret
+        ";
+        assemble(source).unwrap().words
+    }
+
+    #[test]
+    fn memoize_test() {
+        let mut plain = VM::new(program());
+        assert_eq!(plain.run(), State::Halted);
+
+        let mut vm = VM::new(program());
+        vm.enable_memoization();
+        assert_eq!(vm.run(), State::Halted);
+
+        assert_eq!(vm.get_output_words(), vec![210, 210, 325]);
+        assert_eq!(plain.get_output_words(), vec![210, 210, 325]);
+        assert_eq!(vm.get_registers(), plain.get_registers());
+
+        let memo = vm.get_memoizer().unwrap();
+        let triangle = &memo.get_stats()[&23];
+        assert_eq!(triangle.calls, 28);
+        assert_eq!(triangle.hits, 2);
+        assert_eq!(triangle.entries, 26);
+        assert_eq!(plain.get_cycles() - vm.get_cycles(), memo.cycles_saved());
+
+        let peek = &memo.get_stats()[&45];
+        assert_eq!((peek.calls, peek.hits, peek.impure), (2, 0, 2));
+
+        let mut symbols = Symbols::new();
+        symbols.set_function(23, "triangle");
+        assert!(memo
+            .report(&symbols)
+            .starts_with("1 functions memoized, 286 cycles saved\n23 <triangle>: 28 calls"));
+    }
+
+    #[test]
+    fn outside_edit_test() {
+        let source = "
+            setr(#0, 5)
+            call(increment)
+            out(#1)
+            setr(#0, 5)
+            call(increment)
+            out(#1)
+            halt
+            increment: add(#1, #0, 1)
+            ret
+        ";
+        let mut vm = VM::new(assemble(source).unwrap().words);
+        vm.enable_memoization();
+        while vm.get_ip() != 15 {
+            vm.step();
+        }
+        vm.set_register(0, 100);
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.get_output_words(), vec![101, 6]);
+    }
+}
@@ -0,0 +1,505 @@
+use crate::assembler::assemble;
+use crate::symbols::Symbols;
+use crate::vm::{Word, MOD, REGISTERS, REGISTER_LIMIT, VM};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Words to replace at `address`, with the words that must be there first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryEdit {
+    pub address: usize,
+    pub original: Vec<Word>,
+    pub replacement: Vec<Word>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegisterEdit {
+    pub index: usize,
+    /// When given, the register must hold this before the patch applies, and
+    /// reverting puts it back.
+    pub original: Option<Word>,
+    pub value: Word,
+}
+
+/// Changes to a program kept as data, in a line-based file. Each side of a
+/// `memory` edit is either words (`#n` for registers) or instructions in the
+/// assembler's syntax separated by `;`. Addresses, registers and operands may
+/// use names from the symbols it's loaded with, or from a `symbols` file
+/// relative to the patch file:
+///
+/// ```text
+/// # anything after `#` is ignored
+/// symbols challenge.sym
+/// description skip the confirmation
+/// memory confirm_teleporter_call call(confirm_teleporter) -> noop; noop
+/// memory 5495 8 -> 7
+/// register teleporter_energy 0 -> 25734
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patch {
+    pub description: String,
+    pub memory: Vec<MemoryEdit>,
+    pub registers: Vec<RegisterEdit>,
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// Memory doesn't hold the expected words, so this is probably the wrong
+    /// program or the patch is already applied.
+    MemoryMismatch {
+        address: usize,
+        expected: Vec<Word>,
+        found: Vec<Word>,
+    },
+    RegisterMismatch {
+        index: usize,
+        expected: Word,
+        found: Word,
+    },
+}
+
+impl Patch {
+    pub fn load<P: AsRef<Path>>(path: P, symbols: &Symbols) -> Result<Self, PatchError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::parse_in(&text, symbols.clone(), path.parent())
+    }
+
+    /// Parses a patch, resolving names with `symbols` and any `symbols` file
+    /// it names relative to the working directory.
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, PatchError> {
+        Self::parse_in(text, symbols.clone(), None)
+    }
+
+    fn parse_in(
+        text: &str,
+        mut symbols: Symbols,
+        directory: Option<&Path>,
+    ) -> Result<Self, PatchError> {
+        let mut patch = Self::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| PatchError::Parse {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.splitn(2, char::is_whitespace);
+            let directive = fields.next().unwrap_or("");
+            let rest = fields.next().map(str::trim).unwrap_or("");
+
+            match directive {
+                "symbols" => {
+                    let path = match directory {
+                        Some(directory) => directory.join(rest),
+                        None => rest.into(),
+                    };
+                    symbols = Symbols::load(&path).map_err(|message| {
+                        error(format!("unable to load `{}`: {}", path.display(), message))
+                    })?;
+                }
+                "description" => patch.description = rest.to_owned(),
+                "memory" => patch
+                    .memory
+                    .push(parse_memory(rest, &symbols).map_err(error)?),
+                "register" => patch
+                    .registers
+                    .push(parse_register(rest, &symbols).map_err(error)?),
+                _ => return Err(error(format!("unknown directive `{}`", directive))),
+            }
+        }
+
+        Ok(patch)
+    }
+
+    /// Checks that every original word and register value is in place.
+    pub fn check(&self, vm: &VM) -> Result<(), PatchError> {
+        for edit in &self.memory {
+            expect_memory(vm, edit.address, &edit.original)?;
+        }
+        for edit in &self.registers {
+            if let Some(original) = edit.original {
+                expect_register(vm, edit.index, original)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_applied(&self, vm: &VM) -> bool {
+        self.memory
+            .iter()
+            .all(|edit| expect_memory(vm, edit.address, &edit.replacement).is_ok())
+            && self
+                .registers
+                .iter()
+                .all(|edit| expect_register(vm, edit.index, edit.value).is_ok())
+    }
+
+    /// Applies every edit, or none of them if anything isn't as expected.
+    pub fn apply(&self, vm: &mut VM) -> Result<(), PatchError> {
+        self.check(vm)?;
+
+        for edit in &self.memory {
+            write_memory(vm, edit.address, &edit.replacement);
+        }
+        for edit in &self.registers {
+            vm.set_register(edit.index, edit.value);
+        }
+
+        Ok(())
+    }
+
+    /// Undoes `apply`. Registers without an original value are left alone.
+    pub fn revert(&self, vm: &mut VM) -> Result<(), PatchError> {
+        for edit in &self.memory {
+            expect_memory(vm, edit.address, &edit.replacement)?;
+        }
+        for edit in &self.registers {
+            if edit.original.is_some() {
+                expect_register(vm, edit.index, edit.value)?;
+            }
+        }
+
+        for edit in &self.memory {
+            write_memory(vm, edit.address, &edit.original);
+        }
+        for edit in &self.registers {
+            if let Some(original) = edit.original {
+                vm.set_register(edit.index, original);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn expect_memory(vm: &VM, address: usize, expected: &[Word]) -> Result<(), PatchError> {
+    let memory = vm.get_memory();
+    let found = &memory[address.min(memory.len())..(address + expected.len()).min(memory.len())];
+
+    if found == expected {
+        Ok(())
+    } else {
+        Err(PatchError::MemoryMismatch {
+            address,
+            expected: expected.to_vec(),
+            found: found.to_vec(),
+        })
+    }
+}
+
+fn expect_register(vm: &VM, index: usize, expected: Word) -> Result<(), PatchError> {
+    let found = vm.get_registers()[index];
+
+    if found == expected {
+        Ok(())
+    } else {
+        Err(PatchError::RegisterMismatch {
+            index,
+            expected,
+            found,
+        })
+    }
+}
+
+fn write_memory(vm: &mut VM, address: usize, words: &[Word]) {
+    for (offset, &word) in words.iter().enumerate() {
+        vm.set_memory(address + offset, word);
+    }
+}
+
+fn parse_memory(text: &str, symbols: &Symbols) -> Result<MemoryEdit, String> {
+    let (location, edit) = text
+        .split_once(char::is_whitespace)
+        .ok_or("`memory` needs an address and an edit")?;
+    let address = match location.parse::<usize>() {
+        Ok(address) => address,
+        Err(_) => symbols
+            .address_of(location)
+            .ok_or_else(|| format!("unknown address `{}`", location))?,
+    };
+    let (original, replacement) = edit
+        .split_once("->")
+        .ok_or_else(|| format!("`{}` needs `original -> replacement`", edit))?;
+    let original = parse_words(original, symbols)?;
+    let replacement = parse_words(replacement, symbols)?;
+
+    if original.len() != replacement.len() {
+        return Err(format!(
+            "the replacement is {} words but the original is {}",
+            replacement.len(),
+            original.len()
+        ));
+    }
+
+    Ok(MemoryEdit {
+        address,
+        original,
+        replacement,
+    })
+}
+
+fn parse_register(text: &str, symbols: &Symbols) -> Result<RegisterEdit, String> {
+    let (register, values) = text
+        .split_once(char::is_whitespace)
+        .ok_or("`register` needs a register and a value")?;
+    let index = match register.parse::<usize>() {
+        Ok(index) if index < REGISTERS => index,
+        Ok(_) => return Err(format!("there is no register {}", register)),
+        Err(_) => symbols
+            .register_of(register)
+            .ok_or_else(|| format!("unknown register `{}`", register))?,
+    };
+    let value = |text: &str| match text.trim().parse::<Word>() {
+        Ok(value) if value < REGISTER_LIMIT => Ok(value),
+        _ => Err(format!("`{}` is not a value", text.trim())),
+    };
+    let (original, value) = match values.split_once("->") {
+        Some((original, new)) => (Some(value(original)?), value(new)?),
+        None => (None, value(values)?),
+    };
+
+    Ok(RegisterEdit {
+        index,
+        original,
+        value,
+    })
+}
+
+/// Reads plain words, or assembles instructions with names substituted.
+fn parse_words(text: &str, symbols: &Symbols) -> Result<Vec<Word>, String> {
+    let text = text.trim();
+    let word = |token: &str| match token.strip_prefix('#') {
+        Some(register) => register
+            .parse::<Word>()
+            .ok()
+            .filter(|&register| (register as usize) < REGISTERS)
+            .map(|register| MOD + register),
+        None => token.parse::<Word>().ok().filter(|&word| word < MOD),
+    };
+
+    let words: Option<Vec<Word>> = text.split_whitespace().map(word).collect();
+    match words {
+        Some(words) if !words.is_empty() => Ok(words),
+        _ => assemble(&to_source(text, symbols))
+            .map(|assembly| assembly.words)
+            .map_err(|error| format!("`{}`: {}", text, error.message)),
+    }
+}
+
+/// Puts each `;`-separated instruction on its own line and replaces names of
+/// addresses and registers outside quotes with their numbers.
+fn to_source(text: &str, symbols: &Symbols) -> String {
+    let mut source = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut characters = text.chars().peekable();
+
+    while let Some(c) = characters.next() {
+        if let Some(open) = quote {
+            source.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if c == open => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                source.push(c);
+            }
+            ';' => source.push('\n'),
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&next) = characters.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    name.push(next);
+                    characters.next();
+                }
+
+                // Anything followed by `(` is a mnemonic.
+                let resolved = match characters.peek() {
+                    Some('(') => None,
+                    _ => symbols
+                        .address_of(&name)
+                        .map(|address| address.to_string())
+                        .or_else(|| {
+                            symbols
+                                .register_of(&name)
+                                .map(|index| format!("#{}", index))
+                        }),
+                };
+                source.push_str(&resolved.unwrap_or(name));
+            }
+            _ => source.push(c),
+        }
+    }
+
+    source
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io(error) => write!(f, "{}", error),
+            PatchError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            PatchError::MemoryMismatch {
+                address,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {:?} at {} but found {:?}",
+                expected, address, found
+            ),
+            PatchError::RegisterMismatch {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected register {} to hold {} but it holds {}",
+                index, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<io::Error> for PatchError {
+    fn from(error: io::Error) -> Self {
+        PatchError::Io(error)
+    }
+}
+
+impl From<PatchError> for io::Error {
+    fn from(error: PatchError) -> Self {
+        match error {
+            PatchError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> Symbols {
+        Symbols::parse("label 2 check\nfunction 7 helper\nregister 7 energy").unwrap()
+    }
+
+    #[test]
+    fn parse_test() {
+        let text = "
+            # a comment
+            description skip the check
+            memory check jit(energy, helper) -> noop; noop; noop
+            memory 10 #0 1 -> 21 21
+            register energy 0 -> 25734
+            register 1 5
+        ";
+        let patch = Patch::parse(text, &symbols()).unwrap();
+
+        assert_eq!(patch.description, "skip the check");
+        assert_eq!(
+            patch.memory,
+            vec![
+                MemoryEdit {
+                    address: 2,
+                    original: vec![7, 32775, 7],
+                    replacement: vec![21, 21, 21],
+                },
+                MemoryEdit {
+                    address: 10,
+                    original: vec![32768, 1],
+                    replacement: vec![21, 21],
+                },
+            ]
+        );
+        assert_eq!(
+            patch.registers,
+            vec![
+                RegisterEdit {
+                    index: 7,
+                    original: Some(0),
+                    value: 25734,
+                },
+                RegisterEdit {
+                    index: 1,
+                    original: None,
+                    value: 5,
+                },
+            ]
+        );
+
+        let message = |text: &str| match Patch::parse(text, &symbols()) {
+            Err(PatchError::Parse { message, .. }) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert_eq!(
+            message("memory nowhere 1 -> 2"),
+            "unknown address `nowhere`"
+        );
+        assert_eq!(
+            message("memory 0 1 2 -> 3"),
+            "the replacement is 1 words but the original is 2"
+        );
+        assert_eq!(message("register 8 1"), "there is no register 8");
+    }
+
+    #[test]
+    fn apply_test() {
+        let patch = Patch::parse(
+            "memory check jit(energy, helper) -> noop; noop; noop\nregister energy 0 -> 9",
+            &symbols(),
+        )
+        .unwrap();
+        let original = vec![21, 21, 7, 32775, 7, 0, 0, 0];
+        let mut vm = VM::new(original.clone());
+
+        patch.apply(&mut vm).unwrap();
+        assert_eq!(vm.get_memory(), &[21, 21, 21, 21, 21, 0, 0, 0][..]);
+        assert_eq!(vm.get_registers()[7], 9);
+        assert!(patch.is_applied(&vm));
+
+        assert!(matches!(
+            patch.apply(&mut vm),
+            Err(PatchError::MemoryMismatch { address: 2, .. })
+        ));
+
+        patch.revert(&mut vm).unwrap();
+        assert_eq!(vm.get_memory(), &original[..]);
+        assert_eq!(vm.get_registers()[7], 0);
+
+        // Nothing is written when any edit doesn't match.
+        vm.set_register(7, 1);
+        assert!(matches!(
+            patch.apply(&mut vm),
+            Err(PatchError::RegisterMismatch {
+                index: 7,
+                expected: 0,
+                found: 1,
+            })
+        ));
+        assert_eq!(vm.get_memory(), &original[..]);
+    }
+}
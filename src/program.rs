@@ -0,0 +1,356 @@
+use crate::vm::Word;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const FIRST_INVALID_WORD: Word = 32_776;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Binary,
+    Text,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strictness {
+    Strict,
+    Lenient,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidWord {
+    pub address: usize,
+    pub offset: usize,
+    pub value: Word,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadReport {
+    pub format: Format,
+    pub byte_length: usize,
+    pub trailing_byte: Option<(usize, u8)>,
+    pub invalid_words: Vec<InvalidWord>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    words: Vec<Word>,
+    path: Option<PathBuf>,
+    report: LoadReport,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    OddLength { length: usize },
+    InvalidWord(InvalidWord),
+    Parse { line: usize, token: String },
+}
+
+impl LoadReport {
+    pub fn is_clean(&self) -> bool {
+        self.trailing_byte.is_none() && self.invalid_words.is_empty()
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+
+        if let Some((offset, byte)) = self.trailing_byte {
+            warnings.push(format!(
+                "ignored trailing byte {:#04x} at offset {}",
+                byte, offset
+            ));
+        }
+
+        for invalid in &self.invalid_words {
+            warnings.push(format!(
+                "invalid word {} at address {} (offset {})",
+                invalid.value, invalid.address, invalid.offset
+            ));
+        }
+
+        warnings
+    }
+}
+
+impl Program {
+    pub fn load<P: AsRef<Path>>(path: P, strictness: Strictness) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let mut program = Self::from_bytes(&bytes, strictness)?;
+        program.path = Some(path.to_path_buf());
+
+        Ok(program)
+    }
+
+    pub fn from_bytes(bytes: &[u8], strictness: Strictness) -> Result<Self, LoadError> {
+        let (words, report) = match detect_format(bytes) {
+            Format::Binary => decode_binary(bytes, strictness)?,
+            Format::Text => decode_text(bytes, strictness)?,
+        };
+
+        Ok(Self {
+            words,
+            path: None,
+            report,
+        })
+    }
+
+    pub fn from_words(words: Vec<Word>) -> Self {
+        let report = LoadReport {
+            format: Format::Binary,
+            byte_length: words.len() * 2,
+            trailing_byte: None,
+            invalid_words: find_invalid_words(&words, |address| address * 2),
+        };
+
+        Self {
+            words,
+            path: None,
+            report,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn words(&self) -> &[Word] {
+        &self.words
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn report(&self) -> &LoadReport {
+        &self.report
+    }
+
+    pub fn into_words(self) -> Vec<Word> {
+        self.words
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+}
+
+impl From<Program> for Vec<Word> {
+    fn from(program: Program) -> Self {
+        program.into_words()
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::OddLength { length } => {
+                write!(f, "binary has an odd length of {} bytes", length)
+            }
+            LoadError::InvalidWord(invalid) => write!(
+                f,
+                "invalid word {} at address {} (offset {})",
+                invalid.value, invalid.address, invalid.offset
+            ),
+            LoadError::Parse { line, token } => {
+                write!(f, "unable to parse `{}` on line {}", token, line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+impl From<LoadError> for io::Error {
+    fn from(error: LoadError) -> Self {
+        match error {
+            LoadError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+/// Text images are comma or whitespace separated decimal words, as in the
+/// arch-spec's "9,32768,32769,4,19,32768" example; anything else is binary.
+pub fn detect_format(bytes: &[u8]) -> Format {
+    let looks_like_text = !bytes.is_empty()
+        && bytes.iter().any(|byte| byte.is_ascii_digit())
+        && bytes
+            .iter()
+            .all(|&byte| byte.is_ascii_digit() || byte == b',' || byte.is_ascii_whitespace());
+
+    if looks_like_text {
+        Format::Text
+    } else {
+        Format::Binary
+    }
+}
+
+fn decode_binary(
+    bytes: &[u8],
+    strictness: Strictness,
+) -> Result<(Vec<Word>, LoadReport), LoadError> {
+    let trailing_byte = if bytes.len() % 2 == 1 {
+        if strictness == Strictness::Strict {
+            return Err(LoadError::OddLength {
+                length: bytes.len(),
+            });
+        }
+        Some((bytes.len() - 1, bytes[bytes.len() - 1]))
+    } else {
+        None
+    };
+
+    let words: Vec<Word> = bytes
+        .chunks_exact(2)
+        .map(|pair| Word::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    let invalid_words = find_invalid_words(&words, |address| address * 2);
+    check_strictness(&invalid_words, strictness)?;
+
+    let report = LoadReport {
+        format: Format::Binary,
+        byte_length: bytes.len(),
+        trailing_byte,
+        invalid_words,
+    };
+
+    Ok((words, report))
+}
+
+fn decode_text(bytes: &[u8], strictness: Strictness) -> Result<(Vec<Word>, LoadReport), LoadError> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut words: Vec<Word> = vec![];
+    let mut offsets: Vec<usize> = vec![];
+    let mut line_start = 0;
+
+    for (line_index, line) in text.split('\n').enumerate() {
+        let mut column = 0;
+
+        for token in line.split(|c: char| c == ',' || c.is_ascii_whitespace()) {
+            if !token.is_empty() {
+                let word = token.parse::<Word>().map_err(|_| LoadError::Parse {
+                    line: line_index + 1,
+                    token: token.to_owned(),
+                })?;
+                words.push(word);
+                offsets.push(line_start + column);
+            }
+            column += token.len() + 1;
+        }
+
+        line_start += line.len() + 1;
+    }
+
+    let invalid_words = find_invalid_words(&words, |address| offsets[address]);
+    check_strictness(&invalid_words, strictness)?;
+
+    let report = LoadReport {
+        format: Format::Text,
+        byte_length: bytes.len(),
+        trailing_byte: None,
+        invalid_words,
+    };
+
+    Ok((words, report))
+}
+
+fn find_invalid_words<F: Fn(usize) -> usize>(words: &[Word], offset_of: F) -> Vec<InvalidWord> {
+    words
+        .iter()
+        .enumerate()
+        .filter(|(_, &value)| value >= FIRST_INVALID_WORD)
+        .map(|(address, &value)| InvalidWord {
+            address,
+            offset: offset_of(address),
+            value,
+        })
+        .collect()
+}
+
+fn check_strictness(
+    invalid_words: &[InvalidWord],
+    strictness: Strictness,
+) -> Result<(), LoadError> {
+    match (strictness, invalid_words.first()) {
+        (Strictness::Strict, Some(invalid)) => Err(LoadError::InvalidWord(invalid.clone())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_test() {
+        let program =
+            Program::from_bytes(&[9, 0, 0, 128, 1, 128, 4, 0], Strictness::Strict).unwrap();
+
+        assert_eq!(program.words(), &[9, 32768, 32769, 4]);
+        assert_eq!(program.report().format, Format::Binary);
+        assert!(program.report().is_clean());
+    }
+
+    #[test]
+    fn odd_length_test() {
+        let bytes = [19, 0, 65, 0, 0];
+
+        match Program::from_bytes(&bytes, Strictness::Strict) {
+            Err(LoadError::OddLength { length }) => assert_eq!(length, 5),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let program = Program::from_bytes(&bytes, Strictness::Lenient).unwrap();
+        assert_eq!(program.words(), &[19, 65]);
+        assert_eq!(program.report().trailing_byte, Some((4, 0)));
+    }
+
+    #[test]
+    fn invalid_word_test() {
+        let bytes = [21, 0, 8, 128, 0, 0];
+        let expected = InvalidWord {
+            address: 1,
+            offset: 2,
+            value: 32776,
+        };
+
+        match Program::from_bytes(&bytes, Strictness::Strict) {
+            Err(LoadError::InvalidWord(invalid)) => assert_eq!(invalid, expected),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let program = Program::from_bytes(&bytes, Strictness::Lenient).unwrap();
+        assert_eq!(program.report().invalid_words, vec![expected]);
+    }
+
+    #[test]
+    fn text_test() {
+        let program =
+            Program::from_bytes(b"9,32768,32769,4,19,32768\n", Strictness::Strict).unwrap();
+
+        assert_eq!(program.report().format, Format::Text);
+        assert_eq!(program.words(), &[9, 32768, 32769, 4, 19, 32768]);
+        assert_eq!(
+            Program::from_words(program.words().to_vec())
+                .to_bytes()
+                .len(),
+            12
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use crate::ackermann::native_confirmation;
+use crate::patch::Patch;
+use crate::signature::locate_teleporter;
+use crate::symbols::Symbols;
+use crate::vault::explore;
+use crate::vm::{State, Word, REGISTER_LIMIT, VM};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// A line of input for the game.
+    Command(String),
+    /// Applies the patch file at the path.
+    ApplyPatch(String),
+    SetRegister(usize, Word),
+    /// Sets the eighth register to the first value the teleporter's
+    /// confirmation routine accepts.
+    SolveTeleporter,
+    /// Runs the teleporter's confirmation routine natively.
+    HookTeleporter,
+    /// Maps the vault grid from the antechamber and walks the orb to the
+    /// vault door.
+    SolveVault,
+    /// Fails the script unless the game's reply to the last command
+    /// contains the text.
+    ExpectOutput(String),
+    SaveSnapshot(String),
+    Pause,
+}
+
+/// A walkthrough: one game command per line, `#` comments, and `!` directives:
+///
+/// ```text
+/// # get off the beach
+/// take tablet
+/// !expect-output Taken.
+/// !set-register 7 25734
+/// !solve-teleporter
+/// !hook-teleporter
+/// !solve-vault
+/// !apply-patch teleporter.patch
+/// !save-snapshot before-vault.snap
+/// !pause
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Script {
+    /// Each step with the line it came from.
+    pub steps: Vec<(usize, Step)>,
+    /// Names that patch files may use.
+    pub symbols: Symbols,
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Failed { line: usize, message: String },
+}
+
+impl Script {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScriptError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut steps = vec![];
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let step = match line.strip_prefix('!') {
+                Some(directive) => {
+                    parse_directive(directive).map_err(|message| ScriptError::Parse {
+                        line: line_number,
+                        message,
+                    })?
+                }
+                None => Step::Command(line.to_owned()),
+            };
+            steps.push((line_number, step));
+        }
+
+        Ok(Script {
+            steps,
+            symbols: Symbols::new(),
+        })
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Plays the script against `vm`, running the game before each command so
+    /// every command gets its own reply. `pause` is called for `!pause`. The
+    /// script fails at the step where the VM's cycle budget runs out.
+    pub fn run(&self, vm: &mut VM, pause: &mut dyn FnMut(&mut VM)) -> Result<(), ScriptError> {
+        let first_line = self.steps.first().map_or(0, |(line, _)| *line);
+        let mut reply = run_until_input(vm).map_err(|message| ScriptError::Failed {
+            line: first_line,
+            message,
+        })?;
+
+        for (line, step) in &self.steps {
+            let failed = |message: String| ScriptError::Failed {
+                line: *line,
+                message,
+            };
+
+            match step {
+                Step::Command(command) => {
+                    if vm.get_state() != State::WaitingForInput {
+                        return Err(failed(format!(
+                            "the game stopped ({:?}) before `{}`",
+                            vm.get_state(),
+                            command
+                        )));
+                    }
+                    reply = send(vm, command).map_err(failed)?;
+                }
+                Step::ApplyPatch(path) => Patch::load(path, &self.symbols)
+                    .and_then(|patch| patch.apply(vm))
+                    .map_err(|error| failed(format!("unable to apply `{}`: {}", path, error)))?,
+                Step::SetRegister(index, value) => {
+                    vm.set_register(*index, *value);
+                }
+                Step::SolveTeleporter => {
+                    let teleporter = locate_teleporter(vm.get_memory())
+                        .map_err(|error| failed(error.to_string()))?;
+                    let solution = teleporter
+                        .confirmation()
+                        .solve()
+                        .map_err(|error| failed(error.to_string()))?;
+                    let value = solution.values.first().ok_or_else(|| {
+                        failed("no value of the eighth register passes the confirmation".to_owned())
+                    })?;
+                    vm.set_register(7, *value);
+                }
+                Step::HookTeleporter => {
+                    let teleporter = locate_teleporter(vm.get_memory())
+                        .map_err(|error| failed(error.to_string()))?;
+                    vm.set_hook(teleporter.confirm, native_confirmation);
+                }
+                Step::SolveVault => {
+                    let path = explore(vm)
+                        .and_then(|vault| vault.solve())
+                        .map_err(|error| failed(format!("unable to solve the vault: {}", error)))?;
+                    for direction in path {
+                        reply = send(vm, direction.command()).map_err(failed)?;
+                    }
+                }
+                Step::ExpectOutput(text) => {
+                    if !reply.contains(text.as_str()) {
+                        return Err(failed(format!("expected output containing `{}`", text)));
+                    }
+                }
+                Step::SaveSnapshot(path) => vm
+                    .snapshot()
+                    .save(path)
+                    .map_err(|error| failed(format!("unable to save `{}`: {}", path, error)))?,
+                Step::Pause => pause(vm),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn send(vm: &mut VM, command: &str) -> Result<String, String> {
+    for &byte in command.as_bytes() {
+        vm.add_input(byte as Word);
+    }
+    vm.add_input(b'\n' as Word);
+    run_until_input(vm)
+}
+
+fn run_until_input(vm: &mut VM) -> Result<String, String> {
+    if vm.run() == State::Paused {
+        return Err(format!(
+            "the cycle budget ran out at cycle {} before the game asked for input",
+            vm.get_cycles()
+        ));
+    }
+    // A sink that couldn't decode or write the output fails the step that
+    // produced it rather than the end of the session.
+    vm.flush_output()
+        .map_err(|error| format!("unable to write the game's output: {}", error))?;
+    Ok(vm.get_output())
+}
+
+fn parse_directive(directive: &str) -> Result<Step, String> {
+    let mut fields = directive.splitn(2, char::is_whitespace);
+    let name = fields.next().unwrap_or("");
+    let argument = fields.next().map(str::trim).unwrap_or("");
+    let required = || {
+        if argument.is_empty() {
+            Err(format!("`!{}` needs an argument", name))
+        } else {
+            Ok(argument.to_owned())
+        }
+    };
+
+    match name {
+        "apply-patch" => Ok(Step::ApplyPatch(required()?)),
+        "set-register" => {
+            let values: Vec<&str> = argument.split_whitespace().collect();
+            match values[..] {
+                [index, value] => match (index.parse::<usize>(), value.parse::<Word>()) {
+                    (Ok(index), Ok(value)) if index < 8 && value < REGISTER_LIMIT => {
+                        Ok(Step::SetRegister(index, value))
+                    }
+                    _ => Err(format!("`{}` is not a register and value", argument)),
+                },
+                _ => Err("`!set-register` needs a register and a value".to_owned()),
+            }
+        }
+        "solve-teleporter" => Ok(Step::SolveTeleporter),
+        "hook-teleporter" => Ok(Step::HookTeleporter),
+        "solve-vault" => Ok(Step::SolveVault),
+        "expect-output" => Ok(Step::ExpectOutput(required()?)),
+        "save-snapshot" => Ok(Step::SaveSnapshot(required()?)),
+        "pause" => Ok(Step::Pause),
+        _ => Err(format!("unknown directive `!{}`", name)),
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Io(error) => write!(f, "{}", error),
+            ScriptError::Parse { line, message } | ScriptError::Failed { line, message } => {
+                write!(f, "line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(error: io::Error) -> Self {
+        ScriptError::Io(error)
+    }
+}
+
+impl From<ScriptError> for io::Error {
+    fn from(error: ScriptError) -> Self {
+        match error {
+            ScriptError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::streams::{OutputDecoding, WriterSink};
+
+    /// Prompts for lines forever, echoing the first character of each.
+    fn echo_vm() -> VM {
+        let source = "
+            loop: out(\"> \")
+            in(#0)
+            out(#0)
+            skip: in(#1)
+            eq(#1, #1, 10)
+            jf(#1, skip)
+            jmp(loop)
+        ";
+        VM::new(assemble(source).unwrap().words)
+    }
+
+    #[test]
+    fn parse_test() {
+        let script = Script::parse("# intro\nlook\n\n!set-register 7 25734\n!pause").unwrap();
+
+        assert_eq!(
+            script.steps,
+            vec![
+                (2, Step::Command("look".to_owned())),
+                (4, Step::SetRegister(7, 25734)),
+                (5, Step::Pause),
+            ]
+        );
+
+        let message = |text: &str| match Script::parse(text) {
+            Err(ScriptError::Parse { message, .. }) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert_eq!(message("!teleport"), "unknown directive `!teleport`");
+        assert_eq!(
+            message("!expect-output"),
+            "`!expect-output` needs an argument"
+        );
+        assert_eq!(
+            message("!set-register 8 1"),
+            "`8 1` is not a register and value"
+        );
+    }
+
+    #[test]
+    fn output_error_test() {
+        let script = Script::parse("hello\n\u{e9}cho\nworld").unwrap();
+        let mut vm = echo_vm();
+        vm.set_output(WriterSink::new(vec![]).with_decoding(OutputDecoding::Ascii));
+
+        match script.run(&mut vm, &mut |_| {}) {
+            Err(ScriptError::Failed { line: 2, message }) => assert_eq!(
+                message,
+                "unable to write the game's output: output word 195 at position 5 is not valid Ascii"
+            ),
+            other => panic!("expected the second command to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_test() {
+        let script =
+            Script::parse("hello\n!expect-output h\n!pause\nworld\n!expect-output h").unwrap();
+        let mut vm = echo_vm();
+        let mut pauses = 0;
+
+        match script.run(&mut vm, &mut |_| pauses += 1) {
+            Err(ScriptError::Failed { line: 5, message }) => {
+                assert_eq!(message, "expected output containing `h`")
+            }
+            other => panic!("expected the last expectation to fail, got {:?}", other),
+        }
+        assert_eq!(pauses, 1);
+    }
+
+    #[test]
+    fn cycle_budget_test() {
+        let script = Script::parse("# spin\nspin\nlook").unwrap();
+        let mut vm = VM::new(assemble("in(#0)\nspin: jmp(spin)").unwrap().words);
+        vm.set_cycle_budget(Some(1_000));
+
+        match script.run(&mut vm, &mut |_| {}) {
+            Err(ScriptError::Failed { line: 2, message }) => assert_eq!(
+                message,
+                "the cycle budget ran out at cycle 1000 before the game asked for input"
+            ),
+            other => panic!("expected the budget to run out, got {:?}", other),
+        }
+    }
+}
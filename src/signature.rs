@@ -0,0 +1,387 @@
+use crate::ackermann::Confirmation;
+use crate::assembler::{opcode, SHAPES};
+use crate::decoder::decode;
+use crate::symbols::Symbols;
+use crate::vm::{Param, Word, MOD, REGISTERS};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The self-test that every register but the eighth starts at zero.
+pub const EIGHTH_REGISTER_CHECK: &str = "jit(#5, $fail); jit(#6, $fail); jit(#7, $fail)";
+
+/// The teleporter's call to the confirmation routine and the test of its
+/// result.
+pub const TELEPORTER_CONFIRMATION: &str =
+    "setr(#0, $m); setr(#1, $n); call($confirm); eq(#1, #0, $expected); jif(#1, $fail)";
+
+/// The start of the confirmation routine, which tells the teleporter call
+/// apart from other calls set up the same way.
+pub const CONFIRMATION_ROUTINE: &str =
+    "jit(#0, _); add(#0, #1, 1); ret; jit(#1, _); add(#0, #0, 32767); setr(#1, #7)";
+
+#[derive(Clone, Debug, PartialEq)]
+enum OperandPattern {
+    Any,
+    /// Matches anything, but every use of the name must match the same value.
+    Capture(String),
+    Literal(Word),
+    Register(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct InstructionPattern {
+    opcode: Word,
+    operands: Vec<OperandPattern>,
+}
+
+/// A run of instructions in the assembler's syntax, separated by `;`, where an
+/// operand may be `_` to match anything or `$name` to capture it:
+///
+/// ```text
+/// setr(#0, $m); setr(#1, $n); call($confirm); eq(#1, #0, _)
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    instructions: Vec<InstructionPattern>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    /// The address of each matched instruction.
+    pub addresses: Vec<usize>,
+    pub captures: BTreeMap<String, Param>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignatureError {
+    Parse { name: String, message: String },
+    NotFound { name: String },
+    Ambiguous { name: String, addresses: Vec<usize> },
+}
+
+impl Signature {
+    pub fn parse(name: &str, text: &str) -> Result<Self, SignatureError> {
+        let instructions = text
+            .split(';')
+            .map(str::trim)
+            .filter(|instruction| !instruction.is_empty())
+            .map(parse_instruction)
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|message| SignatureError::Parse {
+                name: name.to_owned(),
+                message,
+            })?;
+
+        if instructions.is_empty() {
+            return Err(SignatureError::Parse {
+                name: name.to_owned(),
+                message: "a signature needs at least one instruction".to_owned(),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            instructions,
+        })
+    }
+
+    /// Every address in `memory` where the instructions match, whether or not
+    /// the code there is ever reached.
+    pub fn find(&self, memory: &[Word]) -> Vec<Match> {
+        (0..memory.len())
+            .filter_map(|address| self.match_at(memory, address))
+            .collect()
+    }
+
+    /// The one place the signature matches.
+    pub fn find_unique(&self, memory: &[Word]) -> Result<Match, SignatureError> {
+        unique(&self.name, self.find(memory))
+    }
+
+    pub fn match_at(&self, memory: &[Word], address: usize) -> Option<Match> {
+        let mut found = Match {
+            addresses: vec![],
+            captures: BTreeMap::new(),
+        };
+        let mut address = address;
+
+        for pattern in &self.instructions {
+            let (operation, length) = decode(memory, address).ok()?;
+            if operation.opcode() != pattern.opcode {
+                return None;
+            }
+
+            for (operand, param) in pattern.operands.iter().zip(operation.params()) {
+                let matched = match operand {
+                    OperandPattern::Any => true,
+                    OperandPattern::Literal(word) => param == Param::Literal(*word),
+                    OperandPattern::Register(index) => param == Param::Register(*index),
+                    OperandPattern::Capture(name) => {
+                        *found.captures.entry(name.clone()).or_insert(param) == param
+                    }
+                };
+                if !matched {
+                    return None;
+                }
+            }
+
+            found.addresses.push(address);
+            address += length;
+        }
+
+        Some(found)
+    }
+}
+
+fn unique(name: &str, mut matches: Vec<Match>) -> Result<Match, SignatureError> {
+    match matches.len() {
+        0 => Err(SignatureError::NotFound {
+            name: name.to_owned(),
+        }),
+        1 => Ok(matches.remove(0)),
+        _ => Err(SignatureError::Ambiguous {
+            name: name.to_owned(),
+            addresses: matches.iter().map(|found| found.addresses[0]).collect(),
+        }),
+    }
+}
+
+impl Match {
+    /// A captured literal, such as a jump target.
+    pub fn literal(&self, name: &str) -> Option<Word> {
+        match self.captures.get(name) {
+            Some(Param::Literal(word)) => Some(*word),
+            _ => None,
+        }
+    }
+}
+
+fn parse_instruction(text: &str) -> Result<InstructionPattern, String> {
+    let (mnemonic, arguments) = match text.split_once('(') {
+        Some((mnemonic, rest)) => {
+            let arguments = rest
+                .strip_suffix(')')
+                .ok_or_else(|| format!("`{}` is missing `)`", text))?;
+            (
+                mnemonic.trim(),
+                arguments.split(',').map(str::trim).collect(),
+            )
+        }
+        None => (text, vec![]),
+    };
+    let opcode = opcode(mnemonic).ok_or_else(|| format!("unknown instruction `{}`", mnemonic))?;
+    let count = SHAPES[opcode as usize].0;
+
+    if arguments.len() != count {
+        return Err(format!(
+            "`{}` takes {} operands but was given {}",
+            mnemonic,
+            count,
+            arguments.len()
+        ));
+    }
+
+    let operands = arguments
+        .into_iter()
+        .map(parse_operand)
+        .collect::<Result<_, _>>()?;
+
+    Ok(InstructionPattern { opcode, operands })
+}
+
+fn parse_operand(text: &str) -> Result<OperandPattern, String> {
+    if text == "_" {
+        return Ok(OperandPattern::Any);
+    }
+    if let Some(name) = text.strip_prefix('$') {
+        return Ok(OperandPattern::Capture(name.to_owned()));
+    }
+    if let Some(register) = text.strip_prefix('#') {
+        return match register.parse::<usize>() {
+            Ok(index) if index < REGISTERS => Ok(OperandPattern::Register(index)),
+            _ => Err(format!("`{}` is not a register", text)),
+        };
+    }
+
+    match text.parse::<Word>() {
+        Ok(word) if word < MOD => Ok(OperandPattern::Literal(word)),
+        _ => Err(format!("`{}` is not an operand pattern", text)),
+    }
+}
+
+/// Where the teleporter code sits in one variant of the challenge, and the
+/// arguments it passes to the confirmation routine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Teleporter {
+    pub self_test: usize,
+    pub self_test_failed: usize,
+    pub call_setup: usize,
+    pub call_site: usize,
+    pub check: usize,
+    pub check_failed: usize,
+    pub confirm: usize,
+    pub m: Word,
+    pub n: Word,
+    pub expected: Word,
+}
+
+/// Finds the teleporter code in any variant of the challenge.
+pub fn locate_teleporter(memory: &[Word]) -> Result<Teleporter, SignatureError> {
+    let self_test =
+        Signature::parse("eighth register check", EIGHTH_REGISTER_CHECK)?.find_unique(memory)?;
+    let routine = Signature::parse("confirmation routine", CONFIRMATION_ROUTINE)?;
+    let confirmation = Signature::parse("teleporter confirmation", TELEPORTER_CONFIRMATION)?;
+    let calls = confirmation
+        .find(memory)
+        .into_iter()
+        .filter(|found| {
+            found
+                .literal("confirm")
+                .is_some_and(|confirm| routine.match_at(memory, confirm as usize).is_some())
+        })
+        .collect();
+    let confirmation = unique(&confirmation.name, calls)?;
+    let literal = |found: &Match, name: &str| {
+        found.literal(name).ok_or_else(|| SignatureError::NotFound {
+            name: format!("teleporter confirmation with a literal `{}`", name),
+        })
+    };
+
+    Ok(Teleporter {
+        self_test: self_test.addresses[2],
+        self_test_failed: literal(&self_test, "fail")? as usize,
+        call_setup: confirmation.addresses[0],
+        call_site: confirmation.addresses[2],
+        check: confirmation.addresses[4],
+        check_failed: literal(&confirmation, "fail")? as usize,
+        confirm: literal(&confirmation, "confirm")? as usize,
+        m: literal(&confirmation, "m")?,
+        n: literal(&confirmation, "n")?,
+        expected: literal(&confirmation, "expected")?,
+    })
+}
+
+impl Teleporter {
+    /// The confirmation routine as called here, for the solver.
+    pub fn confirmation(&self) -> Confirmation {
+        Confirmation {
+            m: self.m,
+            n: self.n,
+            modulus: MOD as usize,
+            expected: self.expected,
+        }
+    }
+
+    /// Names the code the way `challenge.sym` does, so patches written
+    /// against those names apply to any variant.
+    pub fn to_symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        symbols.set_register_name(7, "teleporter_energy");
+        symbols.set_label(self.self_test, "check_eighth_register");
+        symbols.set_label(self.self_test_failed, "self_test_failed");
+        symbols.set_label(self.call_setup, "use_teleporter");
+        symbols.set_label(self.call_site, "confirm_teleporter_call");
+        symbols.set_label(self.check, "check_confirmation");
+        symbols.set_label(self.check_failed, "teleporter_unconfirmed");
+        symbols.set_function(self.confirm, "confirm_teleporter");
+        symbols
+    }
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::Parse { name, message } => write!(f, "{}: {}", name, message),
+            SignatureError::NotFound { name } => write!(f, "{} not found", name),
+            SignatureError::Ambiguous { name, addresses } => {
+                write!(f, "{} found at several addresses: {:?}", name, addresses)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn find_test() {
+        let source = "
+            setr(#0, 4)
+            setr(#1, 1)
+            call(confirm)
+            eq(#1, #0, 6)
+            jf(#1, confirm)
+            confirm: ret
+            jt(#1, 2)
+            jt(#2, 3)
+        ";
+        let memory = assemble(source).unwrap().words;
+
+        let signature = Signature::parse("call", TELEPORTER_CONFIRMATION).unwrap();
+        let found = signature.find_unique(&memory).unwrap();
+        assert_eq!(found.addresses, vec![0, 3, 6, 8, 12]);
+        assert_eq!(found.literal("confirm"), Some(15));
+        assert_eq!(found.literal("expected"), Some(6));
+
+        let repeated = Signature::parse("repeated", "jt(_, $target); jt(_, $target)").unwrap();
+        assert_eq!(repeated.find(&memory), vec![]);
+        let jumps = Signature::parse("jumps", "jt(_, _)").unwrap();
+        assert_eq!(
+            jumps.find_unique(&memory),
+            Err(SignatureError::Ambiguous {
+                name: "jumps".to_owned(),
+                addresses: vec![16, 19],
+            })
+        );
+
+        assert_eq!(
+            Signature::parse("bad", "call(_, _)"),
+            Err(SignatureError::Parse {
+                name: "bad".to_owned(),
+                message: "`call` takes 1 operands but was given 2".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn locate_test() {
+        let source = "
+            jt(#5, fail)
+            jt(#6, fail)
+            jt(#7, fail)
+            setr(#0, 2)
+            setr(#1, 3)
+            call(decoy)
+            eq(#1, #0, 9)
+            jf(#1, fail)
+            setr(#0, 4)
+            setr(#1, 1)
+            call(confirm)
+            eq(#1, #0, 6)
+            jf(#1, fail)
+            fail: halt
+            decoy: ret
+            confirm: jt(#0, next)
+            add(#0, #1, 1)
+            ret
+            next: jt(#1, fail)
+            add(#0, #0, 32767)
+            setr(#1, #7)
+        ";
+        let memory = assemble(source).unwrap().words;
+
+        let teleporter = locate_teleporter(&memory).unwrap();
+        assert_eq!((teleporter.m, teleporter.n, teleporter.expected), (4, 1, 6));
+        assert_eq!(teleporter.call_site, 30);
+
+        let symbols = teleporter.to_symbols();
+        assert_eq!(symbols.address_of("check_eighth_register"), Some(6));
+        assert_eq!(symbols.address_of("confirm_teleporter"), Some(41));
+        assert_eq!(symbols.register_of("teleporter_energy"), Some(7));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::vm::{State, VmError, Word};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"SYNSNAP\0";
+pub const VERSION: u16 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub state: State,
+    pub cycles: u64,
+    pub ip: usize,
+    pub registers: Vec<Word>,
+    pub stack: Vec<Word>,
+    pub memory: Vec<Word>,
+    pub input: Vec<Word>,
+    pub output: Vec<Word>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidTag { kind: &'static str, tag: u8 },
+}
+
+impl Snapshot {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        write_state(&mut bytes, &self.state);
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&(self.ip as u64).to_le_bytes());
+
+        for words in &[
+            &self.registers,
+            &self.stack,
+            &self.memory,
+            &self.input,
+            &self.output,
+        ] {
+            write_words(&mut bytes, words);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        Ok(Self {
+            state: reader.state()?,
+            cycles: reader.u64()?,
+            ip: reader.u64()? as usize,
+            registers: reader.words()?,
+            stack: reader.words()?,
+            memory: reader.words()?,
+            input: reader.words()?,
+            output: reader.words()?,
+        })
+    }
+}
+
+fn write_words(bytes: &mut Vec<u8>, words: &[Word]) {
+    bytes.extend_from_slice(&(words.len() as u32).to_le_bytes());
+
+    for word in words {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+}
+
+fn write_state(bytes: &mut Vec<u8>, state: &State) {
+    match state {
+        State::Initialized => bytes.push(0),
+        State::Running => bytes.push(1),
+        State::WaitingForInput => bytes.push(2),
+        State::Paused => bytes.push(3),
+        State::Halted => bytes.push(4),
+        State::Errored(error) => {
+            let (tag, ip, value) = match *error {
+                VmError::InvalidRegister { ip, word } => (0, ip, word as u64),
+                VmError::InvalidOpcode { ip, opcode } => (1, ip, opcode as u64),
+                VmError::IpOutOfBounds { ip, address } => (2, ip, address as u64),
+                VmError::AddressOutOfBounds { ip, address } => (3, ip, address as u64),
+                VmError::DivisionByZero { ip, dividend } => (4, ip, dividend as u64),
+                VmError::WriteToLiteral { ip, literal } => (5, ip, literal as u64),
+                VmError::EmptyStack { ip } => (6, ip, 0),
+            };
+
+            bytes.push(5);
+            bytes.push(tag);
+            bytes.extend_from_slice(&(ip as u64).to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.position + count;
+        let slice = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(SnapshotError::Truncated)?;
+        self.position = end;
+
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut buffer = [0; 4];
+        buffer.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buffer))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut buffer = [0; 8];
+        buffer.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buffer))
+    }
+
+    fn words(&mut self) -> Result<Vec<Word>, SnapshotError> {
+        let count = self.u32()? as usize;
+        let bytes = self.take(count * 2)?;
+
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|pair| Word::from_le_bytes([pair[0], pair[1]]))
+            .collect())
+    }
+
+    fn state(&mut self) -> Result<State, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(State::Initialized),
+            1 => Ok(State::Running),
+            2 => Ok(State::WaitingForInput),
+            3 => Ok(State::Paused),
+            4 => Ok(State::Halted),
+            5 => {
+                let tag = self.u8()?;
+                let ip = self.u64()? as usize;
+                let value = self.u64()?;
+
+                let error = match tag {
+                    0 => VmError::InvalidRegister {
+                        ip,
+                        word: value as Word,
+                    },
+                    1 => VmError::InvalidOpcode {
+                        ip,
+                        opcode: value as Word,
+                    },
+                    2 => VmError::IpOutOfBounds {
+                        ip,
+                        address: value as usize,
+                    },
+                    3 => VmError::AddressOutOfBounds {
+                        ip,
+                        address: value as Word,
+                    },
+                    4 => VmError::DivisionByZero {
+                        ip,
+                        dividend: value as Word,
+                    },
+                    5 => VmError::WriteToLiteral {
+                        ip,
+                        literal: value as Word,
+                    },
+                    6 => VmError::EmptyStack { ip },
+                    tag => return Err(SnapshotError::InvalidTag { kind: "error", tag }),
+                };
+
+                Ok(State::Errored(error))
+            }
+            tag => Err(SnapshotError::InvalidTag { kind: "state", tag }),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "{}", error),
+            SnapshotError::BadMagic => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::InvalidTag { kind, tag } => write!(f, "invalid {} tag {}", kind, tag),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<SnapshotError> for io::Error {
+    fn from(error: SnapshotError) -> Self {
+        match error {
+            SnapshotError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn round_trip_test() {
+        let mut vm = VM::new(vec![2, 7, 19, 72, 20, 32768, 19, 32768, 0]);
+        vm.add_input(b'a' as Word);
+        vm.add_input(b'b' as Word);
+        assert_eq!(vm.run_for(4), State::Paused);
+
+        let snapshot = vm.snapshot();
+        let restored = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(restored, snapshot);
+
+        let mut copy = VM::from_snapshot(restored);
+        assert_eq!(copy.get_ip(), vm.get_ip());
+        assert_eq!(copy.get_cycles(), vm.get_cycles());
+        assert_eq!(copy.run(), State::Halted);
+        assert_eq!(copy.get_output(), "Ha");
+        assert_eq!(copy.snapshot().input, vec![b'b' as Word]);
+    }
+
+    #[test]
+    fn errored_state_test() {
+        let mut vm = VM::new(vec![11, 32768, 1, 0]);
+        vm.run();
+
+        let bytes = vm.snapshot().to_bytes();
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            snapshot.state,
+            State::Errored(VmError::DivisionByZero { ip: 0, dividend: 1 })
+        );
+    }
+
+    #[test]
+    fn invalid_snapshot_test() {
+        assert!(matches!(
+            Snapshot::from_bytes(b"nope"),
+            Err(SnapshotError::Truncated)
+        ));
+        assert!(matches!(
+            Snapshot::from_bytes(b"NOTASNAPSHOT"),
+            Err(SnapshotError::BadMagic)
+        ));
+
+        let mut bytes = VM::new(vec![0]).snapshot().to_bytes();
+        bytes[8] = 99;
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes),
+            Err(SnapshotError::UnsupportedVersion(99))
+        ));
+    }
+}
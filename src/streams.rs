@@ -0,0 +1,553 @@
+use crate::vm::Word;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Stdin, Stdout, Write};
+use std::path::Path;
+
+/// How output words are turned into text or bytes. The VM itself only ever
+/// deals in raw words.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputDecoding {
+    /// Only 0-127; anything else is an error.
+    Ascii,
+    /// 0-255 map to the same byte or code point; anything else is an error.
+    Latin1,
+    /// ASCII passes through and everything else becomes U+FFFD.
+    Lossy,
+    /// Every word as its decimal value, one per line.
+    Numeric,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputError {
+    pub decoding: OutputDecoding,
+    pub position: usize,
+    pub value: Word,
+}
+
+impl OutputDecoding {
+    pub fn decode(self, words: &[Word]) -> Result<String, OutputError> {
+        let mut text = String::with_capacity(words.len());
+
+        for (position, &word) in words.iter().enumerate() {
+            match self {
+                OutputDecoding::Ascii if word < 128 => text.push(word as u8 as char),
+                OutputDecoding::Latin1 if word < 256 => text.push(word as u8 as char),
+                OutputDecoding::Ascii | OutputDecoding::Latin1 => {
+                    return Err(self.error(position, word))
+                }
+                OutputDecoding::Lossy if word < 128 => text.push(word as u8 as char),
+                OutputDecoding::Lossy => text.push(char::REPLACEMENT_CHARACTER),
+                OutputDecoding::Numeric => text.push_str(&format!("{}\n", word)),
+            }
+        }
+
+        Ok(text)
+    }
+
+    /// Like `decode`, but Latin-1 produces one byte per word rather than
+    /// UTF-8, so binary output comes through byte for byte.
+    pub fn encode(self, words: &[Word]) -> Result<Vec<u8>, OutputError> {
+        match self {
+            OutputDecoding::Latin1 => words
+                .iter()
+                .enumerate()
+                .map(|(position, &word)| match word {
+                    0..=255 => Ok(word as u8),
+                    _ => Err(self.error(position, word)),
+                })
+                .collect(),
+            _ => self.decode(words).map(String::into_bytes),
+        }
+    }
+
+    fn error(self, position: usize, value: Word) -> OutputError {
+        OutputError {
+            decoding: self,
+            position,
+            value,
+        }
+    }
+}
+
+/// Where `in` gets its words from.
+pub trait InputSource: fmt::Debug {
+    /// The next word, or `None` when nothing is available yet, in which case
+    /// the VM waits for input.
+    fn read(&mut self) -> Option<Word>;
+
+    /// Puts a word back so it is read next; used when stepping backwards.
+    fn unread(&mut self, word: Word);
+
+    /// Queues a word after everything already buffered.
+    fn push(&mut self, word: Word);
+
+    /// Words buffered but not yet read, as saved in snapshots.
+    fn pending(&self) -> Vec<Word>;
+
+    /// Replaces whatever is buffered, as when restoring a snapshot.
+    fn set_pending(&mut self, words: Vec<Word>);
+}
+
+/// Where `out` sends its words.
+pub trait OutputSink: fmt::Debug {
+    fn write(&mut self, word: Word);
+
+    /// Takes back the last word if it hasn't left the sink yet; used when
+    /// stepping backwards.
+    fn unwrite(&mut self) -> Option<Word> {
+        None
+    }
+
+    /// Drains the words the sink is holding on to. Sinks that write straight
+    /// through have nothing to return.
+    fn take(&mut self) -> Vec<Word> {
+        vec![]
+    }
+
+    /// Words held but not yet taken, as saved in snapshots.
+    fn pending(&self) -> Vec<Word> {
+        vec![]
+    }
+
+    /// Restores held words from a snapshot. Sinks that don't hold on to
+    /// anything just write them.
+    fn set_pending(&mut self, words: Vec<Word>) {
+        for word in words {
+            self.write(word);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryInput {
+    words: VecDeque<Word>,
+}
+
+impl MemoryInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        for &byte in line.as_bytes() {
+            self.words.push_back(byte as Word);
+        }
+        self.words.push_back(b'\n' as Word);
+    }
+}
+
+impl From<&str> for MemoryInput {
+    fn from(text: &str) -> Self {
+        Self {
+            words: text.bytes().map(|byte| byte as Word).collect(),
+        }
+    }
+}
+
+impl InputSource for MemoryInput {
+    fn read(&mut self) -> Option<Word> {
+        self.words.pop_front()
+    }
+
+    fn unread(&mut self, word: Word) {
+        self.words.push_front(word);
+    }
+
+    fn push(&mut self, word: Word) {
+        self.words.push_back(word);
+    }
+
+    fn pending(&self) -> Vec<Word> {
+        self.words.iter().copied().collect()
+    }
+
+    fn set_pending(&mut self, words: Vec<Word>) {
+        self.words = words.into();
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryOutput {
+    words: Vec<Word>,
+}
+
+impl MemoryOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OutputSink for MemoryOutput {
+    fn write(&mut self, word: Word) {
+        self.words.push(word);
+    }
+
+    fn unwrite(&mut self) -> Option<Word> {
+        self.words.pop()
+    }
+
+    fn take(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.words)
+    }
+
+    fn pending(&self) -> Vec<Word> {
+        self.words.clone()
+    }
+
+    fn set_pending(&mut self, words: Vec<Word>) {
+        self.words = words;
+    }
+}
+
+/// Reads a line at a time from any reader, so a blocking reader like stdin
+/// only blocks when the game actually asks for input. End of file leaves the
+/// VM waiting for input.
+#[derive(Debug)]
+pub struct ReaderSource<R: BufRead> {
+    reader: R,
+    buffer: VecDeque<Word>,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> ReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    /// The read error that ended input, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl ReaderSource<BufReader<Stdin>> {
+    pub fn stdin() -> Self {
+        Self::new(BufReader::new(io::stdin()))
+    }
+}
+
+impl ReaderSource<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead + fmt::Debug> InputSource for ReaderSource<R> {
+    fn read(&mut self) -> Option<Word> {
+        if self.buffer.is_empty() && self.error.is_none() {
+            let mut line = vec![];
+            match self.reader.read_until(b'\n', &mut line) {
+                Ok(_) => self.buffer.extend(line.into_iter().map(Word::from)),
+                Err(error) => self.error = Some(error),
+            }
+        }
+
+        self.buffer.pop_front()
+    }
+
+    fn unread(&mut self, word: Word) {
+        self.buffer.push_front(word);
+    }
+
+    fn push(&mut self, word: Word) {
+        self.buffer.push_back(word);
+    }
+
+    fn pending(&self) -> Vec<Word> {
+        self.buffer.iter().copied().collect()
+    }
+
+    fn set_pending(&mut self, words: Vec<Word>) {
+        self.buffer = words.into();
+    }
+}
+
+/// Encodes each word with its `OutputDecoding`, lossy unless told otherwise.
+/// Encoding and write failures are kept until `flush` rather than
+/// interrupting the VM, and nothing more is written after one, so frontends
+/// flush whenever the VM stops to report them promptly.
+#[derive(Debug)]
+pub struct WriterSink<W: Write> {
+    writer: W,
+    decoding: OutputDecoding,
+    written: usize,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            decoding: OutputDecoding::Lossy,
+            written: 0,
+            error: None,
+        }
+    }
+
+    pub fn with_decoding(mut self, decoding: OutputDecoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl WriterSink<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl WriterSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + fmt::Debug> OutputSink for WriterSink<W> {
+    fn write(&mut self, word: Word) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = match self.decoding.encode(&[word]) {
+            Ok(bytes) => self.writer.write_all(&bytes),
+            Err(error) => Err(OutputError {
+                position: self.written,
+                ..error
+            }
+            .into()),
+        };
+        let result = if word == b'\n' as Word {
+            result.and_then(|_| self.writer.flush())
+        } else {
+            result
+        };
+
+        self.written += 1;
+        if let Err(error) = result {
+            self.error = Some(error);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+/// Sends every word to two sinks. Only the first one is asked for held
+/// words, so put the buffering sink first.
+#[derive(Debug)]
+pub struct TeeSink<A: OutputSink, B: OutputSink> {
+    first: A,
+    second: B,
+}
+
+impl<A: OutputSink, B: OutputSink> TeeSink<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: OutputSink, B: OutputSink> OutputSink for TeeSink<A, B> {
+    fn write(&mut self, word: Word) {
+        self.first.write(word);
+        self.second.write(word);
+    }
+
+    fn unwrite(&mut self) -> Option<Word> {
+        self.second.unwrite();
+        self.first.unwrite()
+    }
+
+    fn take(&mut self) -> Vec<Word> {
+        self.second.take();
+        self.first.take()
+    }
+
+    fn pending(&self) -> Vec<Word> {
+        self.first.pending()
+    }
+
+    fn set_pending(&mut self, words: Vec<Word>) {
+        self.first.set_pending(words);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush().and(self.second.flush())
+    }
+}
+
+/// Copies every word read from `source` into `recording`, e.g. to keep a
+/// transcript of what was typed.
+#[derive(Debug)]
+pub struct RecordingSource<S: InputSource, O: OutputSink> {
+    source: S,
+    recording: O,
+}
+
+impl<S: InputSource, O: OutputSink> RecordingSource<S, O> {
+    pub fn new(source: S, recording: O) -> Self {
+        Self { source, recording }
+    }
+
+    pub fn into_inner(self) -> (S, O) {
+        (self.source, self.recording)
+    }
+}
+
+impl<S: InputSource, O: OutputSink> InputSource for RecordingSource<S, O> {
+    fn read(&mut self) -> Option<Word> {
+        let word = self.source.read()?;
+        self.recording.write(word);
+        Some(word)
+    }
+
+    fn unread(&mut self, word: Word) {
+        self.recording.unwrite();
+        self.source.unread(word);
+    }
+
+    fn push(&mut self, word: Word) {
+        self.source.push(word);
+    }
+
+    fn pending(&self) -> Vec<Word> {
+        self.source.pending()
+    }
+
+    fn set_pending(&mut self, words: Vec<Word>) {
+        self.source.set_pending(words);
+    }
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "output word {} at position {} is not valid {:?}",
+            self.value, self.position, self.decoding
+        )
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+impl From<OutputError> for io::Error {
+    fn from(error: OutputError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+/// A writer whose bytes tests can still read after handing it to a sink or
+/// tracer.
+#[cfg(test)]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedBuffer(pub(crate) std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn decoding_test() {
+        let words = [b'h' as Word, 233, 300];
+
+        assert_eq!(
+            OutputDecoding::Ascii.decode(&words),
+            Err(OutputError {
+                decoding: OutputDecoding::Ascii,
+                position: 1,
+                value: 233,
+            })
+        );
+        assert_eq!(
+            OutputDecoding::Latin1.decode(&words[..2]).unwrap(),
+            "h\u{e9}"
+        );
+        assert_eq!(
+            OutputDecoding::Latin1.encode(&words[..2]).unwrap(),
+            vec![b'h', 233]
+        );
+        assert_eq!(
+            OutputDecoding::Latin1.encode(&words).unwrap_err().position,
+            2
+        );
+        assert_eq!(
+            OutputDecoding::Lossy.decode(&words).unwrap(),
+            "h\u{fffd}\u{fffd}"
+        );
+        assert_eq!(
+            OutputDecoding::Numeric.decode(&words).unwrap(),
+            "104\n233\n300\n"
+        );
+
+        let mut sink = WriterSink::new(vec![]).with_decoding(OutputDecoding::Ascii);
+        for &word in &words {
+            sink.write(word);
+        }
+        assert!(sink.flush().is_err());
+        assert_eq!(sink.into_inner(), b"h".to_vec());
+    }
+
+    #[test]
+    fn reader_source_test() {
+        let mut source = ReaderSource::new(Cursor::new("ab\nc"));
+
+        assert_eq!(source.read(), Some(b'a' as Word));
+        assert_eq!(source.pending(), vec![b'b' as Word, b'\n' as Word]);
+        source.unread(b'a' as Word);
+
+        let words: Vec<Word> = std::iter::from_fn(|| source.read()).collect();
+        assert_eq!(words, "ab\nc".bytes().map(Word::from).collect::<Vec<_>>());
+        assert_eq!(source.read(), None);
+    }
+
+    #[test]
+    fn tee_and_recording_test() {
+        let mut sink = TeeSink::new(MemoryOutput::new(), WriterSink::new(vec![]));
+        let mut source = RecordingSource::new(MemoryInput::from("hi"), MemoryOutput::new());
+
+        while let Some(word) = source.read() {
+            sink.write(word);
+        }
+        sink.flush().unwrap();
+
+        let (_, mut recording) = source.into_inner();
+        assert_eq!(recording.take(), vec![b'h' as Word, b'i' as Word]);
+        assert_eq!(sink.take(), vec![b'h' as Word, b'i' as Word]);
+        assert_eq!(sink.into_inner().1.into_inner(), b"hi".to_vec());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::vm::REGISTERS;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataType {
+    Code,
+    Text,
+    Words,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Label,
+    Function,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+}
+
+/// Names and notes for addresses and registers, kept in a line-based file:
+///
+/// ```text
+/// # lines starting with `#` are skipped; a `#` later in a line is kept
+/// function 6027 confirm_teleporter
+/// label 5489 teleporter_call
+/// comment 5489 calls confirm_teleporter with #0=4, #1=1
+/// type 6068 text
+/// register 7 energy
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    symbols: BTreeMap<usize, Symbol>,
+    comments: BTreeMap<usize, String>,
+    types: BTreeMap<usize, DataType>,
+    registers: BTreeMap<usize, String>,
+}
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SymbolError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SymbolError> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = Self::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| SymbolError::Parse {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let directive = fields.next().unwrap_or("");
+            let number = fields
+                .next()
+                .and_then(|field| field.parse::<usize>().ok())
+                .ok_or_else(|| error(format!("`{}` needs an address", directive)))?;
+            let rest = fields.next().map(str::trim).unwrap_or("");
+
+            match directive {
+                "label" | "function" | "register" if !is_identifier(rest) => {
+                    return Err(error(format!("`{}` is not a valid name", rest)));
+                }
+                "label" => symbols.set_label(number, rest),
+                "function" => symbols.set_function(number, rest),
+                "register" if number < REGISTERS => symbols.set_register_name(number, rest),
+                "register" => return Err(error(format!("there is no register {}", number))),
+                "comment" => symbols.set_comment(number, rest),
+                "type" => {
+                    let data_type = match rest {
+                        "code" => DataType::Code,
+                        "text" => DataType::Text,
+                        "words" => DataType::Words,
+                        _ => return Err(error(format!("unknown type `{}`", rest))),
+                    };
+                    symbols.set_data_type(number, data_type);
+                }
+                _ => return Err(error(format!("unknown directive `{}`", directive))),
+            }
+
+            if symbols.has_duplicate_name(number) {
+                return Err(error(format!("the name `{}` is used twice", rest)));
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        for (index, name) in &self.registers {
+            text.push_str(&format!("register {} {}\n", index, name));
+        }
+        for (address, symbol) in &self.symbols {
+            let directive = match symbol.kind {
+                SymbolKind::Label => "label",
+                SymbolKind::Function => "function",
+            };
+            text.push_str(&format!("{} {} {}\n", directive, address, symbol.name));
+        }
+        for (address, comment) in &self.comments {
+            text.push_str(&format!("comment {} {}\n", address, comment));
+        }
+        for (address, data_type) in &self.types {
+            let name = match data_type {
+                DataType::Code => "code",
+                DataType::Text => "text",
+                DataType::Words => "words",
+            };
+            text.push_str(&format!("type {} {}\n", address, name));
+        }
+
+        text
+    }
+
+    fn has_duplicate_name(&self, address: usize) -> bool {
+        match self.symbols.get(&address) {
+            Some(symbol) => self.address_of(&symbol.name) != Some(address),
+            None => false,
+        }
+    }
+
+    pub fn set_label(&mut self, address: usize, name: &str) {
+        self.symbols.insert(
+            address,
+            Symbol {
+                name: name.to_owned(),
+                kind: SymbolKind::Label,
+            },
+        );
+    }
+
+    pub fn set_function(&mut self, address: usize, name: &str) {
+        self.symbols.insert(
+            address,
+            Symbol {
+                name: name.to_owned(),
+                kind: SymbolKind::Function,
+            },
+        );
+    }
+
+    pub fn set_comment(&mut self, address: usize, comment: &str) {
+        self.comments.insert(address, comment.to_owned());
+    }
+
+    pub fn set_data_type(&mut self, address: usize, data_type: DataType) {
+        self.types.insert(address, data_type);
+    }
+
+    pub fn set_register_name(&mut self, index: usize, name: &str) {
+        self.registers.insert(index, name.to_owned());
+    }
+
+    pub fn get_symbol(&self, address: usize) -> Option<&Symbol> {
+        self.symbols.get(&address)
+    }
+
+    pub fn get_name(&self, address: usize) -> Option<&str> {
+        self.symbols
+            .get(&address)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    pub fn get_comment(&self, address: usize) -> Option<&str> {
+        self.comments.get(&address).map(String::as_str)
+    }
+
+    pub fn get_data_type(&self, address: usize) -> Option<DataType> {
+        self.types.get(&address).copied()
+    }
+
+    pub fn get_register_name(&self, index: usize) -> Option<&str> {
+        self.registers.get(&index).map(String::as_str)
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .find(|(_, symbol)| symbol.name == name)
+            .map(|(&address, _)| address)
+    }
+
+    pub fn register_of(&self, name: &str) -> Option<usize> {
+        self.registers
+            .iter()
+            .find(|(_, register)| *register == name)
+            .map(|(&index, _)| index)
+    }
+
+    /// Whether anything at all is recorded for `address`.
+    pub fn is_annotated(&self, address: usize) -> bool {
+        self.symbols.contains_key(&address)
+            || self.comments.contains_key(&address)
+            || self.types.contains_key(&address)
+    }
+
+    /// Addresses known to hold code: functions and `type <address> code`.
+    pub fn code_addresses(&self) -> Vec<usize> {
+        let functions = self
+            .symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.kind == SymbolKind::Function)
+            .map(|(&address, _)| address);
+        let code = self
+            .types
+            .iter()
+            .filter(|(_, &data_type)| data_type == DataType::Code)
+            .map(|(&address, _)| address);
+
+        functions.chain(code).collect()
+    }
+
+    /// Formats `address` with its name, falling back to the number.
+    pub fn describe(&self, address: usize) -> String {
+        match self.get_name(address) {
+            Some(name) => format!("{} <{}>", address, name),
+            None => address.to_string(),
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut characters = name.chars();
+    characters
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && characters.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(error) => write!(f, "{}", error),
+            SymbolError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(error: io::Error) -> Self {
+        SymbolError::Io(error)
+    }
+}
+
+impl From<SymbolError> for io::Error {
+    fn from(error: SymbolError) -> Self {
+        match error {
+            SymbolError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "
+        # teleporter notes
+        register 7 energy
+        function 6027 confirm_teleporter
+        label 5489 teleporter_call
+        comment 5489 calls confirm_teleporter with #0=4, #1=1
+        type 6068 text
+    ";
+
+    #[test]
+    fn parse_test() {
+        let symbols = Symbols::parse(SAMPLE).unwrap();
+
+        assert_eq!(symbols.get_name(6027), Some("confirm_teleporter"));
+        assert_eq!(symbols.address_of("teleporter_call"), Some(5489));
+        assert_eq!(
+            symbols.get_comment(5489),
+            Some("calls confirm_teleporter with #0=4, #1=1")
+        );
+        assert_eq!(symbols.get_data_type(6068), Some(DataType::Text));
+        assert_eq!(symbols.register_of("energy"), Some(7));
+        assert_eq!(symbols.code_addresses(), vec![6027]);
+        assert_eq!(symbols.describe(6027), "6027 <confirm_teleporter>");
+        assert_eq!(Symbols::parse(&symbols.to_text()).unwrap(), symbols);
+    }
+
+    #[test]
+    fn error_test() {
+        let message = |text: &str| match Symbols::parse(text) {
+            Err(SymbolError::Parse { message, .. }) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+
+        assert_eq!(message("label x y"), "`label` needs an address");
+        assert_eq!(message("label 1 2bad"), "`2bad` is not a valid name");
+        assert_eq!(message("register 8 r8"), "there is no register 8");
+        assert_eq!(message("type 1 float"), "unknown type `float`");
+        assert_eq!(
+            message("label 1 a\nlabel 2 a"),
+            "the name `a` is used twice"
+        );
+        assert_eq!(message("rename 1 a"), "unknown directive `rename`");
+    }
+}
@@ -0,0 +1,579 @@
+use crate::decoder::decode;
+use crate::symbols::Symbols;
+use crate::vm::{Operation, Param, Word, MNEMONICS};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceFilter {
+    pub addresses: Option<Range<usize>>,
+    pub cycles: Option<Range<u64>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    Register {
+        index: usize,
+        old: Word,
+        new: Word,
+    },
+    Memory {
+        address: usize,
+        old: Word,
+        new: Word,
+    },
+    Push(Word),
+    Pop(Word),
+    Input(Word),
+    Output(Word),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub ip: usize,
+    pub operation: Operation,
+    pub values: Vec<Word>,
+    pub effects: Vec<Effect>,
+    pub registers: Vec<Word>,
+    pub stack_depth: usize,
+}
+
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    symbols: Symbols,
+    error: Option<io::Error>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, cycle: u64, ip: usize) -> bool {
+        self.addresses
+            .as_ref()
+            .is_none_or(|addresses| addresses.contains(&ip))
+            && self
+                .cycles
+                .as_ref()
+                .is_none_or(|cycles| cycles.contains(&cycle))
+    }
+}
+
+impl TraceEntry {
+    pub fn to_text(&self) -> String {
+        self.to_annotated_text(&Symbols::new())
+    }
+
+    /// Like `to_text`, but with named addresses and registers shown by name.
+    pub fn to_annotated_text(&self, symbols: &Symbols) -> String {
+        let register = |index: usize| match symbols.get_register_name(index) {
+            Some(name) => name.to_owned(),
+            None => format!("#{}", index),
+        };
+        let target = self.operation.target();
+        let params: Vec<String> = self
+            .operation
+            .params()
+            .iter()
+            .zip(&self.values)
+            .map(|(param, value)| match param {
+                Param::Literal(literal) if Some(*param) == target => {
+                    symbols.describe(*literal as usize)
+                }
+                Param::Literal(literal) => format!("{}", literal),
+                Param::Register(index) => format!("{}={}", register(*index), value),
+            })
+            .collect();
+        let effects: Vec<String> = self
+            .effects
+            .iter()
+            .map(|effect| match effect {
+                Effect::Register { index, old, new } => {
+                    format!("{} {}->{}", register(*index), old, new)
+                }
+                effect => effect.to_string(),
+            })
+            .collect();
+
+        let location = match symbols.get_name(self.ip) {
+            Some(name) => format!("{:>5} <{}>", self.ip, name),
+            None => format!("{:>5}", self.ip),
+        };
+        let mut text = format!(
+            "{:>10} {}: {}",
+            self.cycle,
+            location,
+            self.operation.mnemonic()
+        );
+
+        if !params.is_empty() {
+            text.push(' ');
+            text.push_str(&params.join(" "));
+        }
+
+        if !effects.is_empty() {
+            text.push_str(" | ");
+            text.push_str(&effects.join(", "));
+        }
+
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let args: Vec<Word> = self
+            .operation
+            .params()
+            .iter()
+            .map(|param| match param {
+                Param::Literal(literal) => *literal,
+                Param::Register(index) => 32_768 + *index as Word,
+            })
+            .collect();
+        let effects: Vec<String> = self
+            .effects
+            .iter()
+            .map(|effect| match effect {
+                Effect::Register { index, old, new } => {
+                    format!("{{\"reg\":{},\"old\":{},\"new\":{}}}", index, old, new)
+                }
+                Effect::Memory { address, old, new } => {
+                    format!("{{\"mem\":{},\"old\":{},\"new\":{}}}", address, old, new)
+                }
+                Effect::Push(value) => format!("{{\"push\":{}}}", value),
+                Effect::Pop(value) => format!("{{\"pop\":{}}}", value),
+                Effect::Input(value) => format!("{{\"in\":{}}}", value),
+                Effect::Output(value) => format!("{{\"out\":{}}}", value),
+            })
+            .collect();
+
+        format!(
+            "{{\"cycle\":{},\"ip\":{},\"op\":\"{}\",\"args\":{},\"values\":{},\"effects\":[{}],\"regs\":{},\"sp\":{}}}",
+            self.cycle,
+            self.ip,
+            self.operation.mnemonic(),
+            json_array(&args),
+            json_array(&self.values),
+            effects.join(","),
+            json_array(&self.registers),
+            self.stack_depth
+        )
+    }
+
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let value = Json::parse(line)?;
+        let mnemonic = value.field("op")?.as_str()?;
+        let opcode = MNEMONICS
+            .iter()
+            .position(|candidate| *candidate == mnemonic)
+            .ok_or_else(|| format!("unknown op `{}`", mnemonic))?;
+
+        let mut words = vec![opcode as Word];
+        words.extend(value.field("args")?.as_words()?);
+        let (operation, _) = decode(&words, 0).map_err(|error| error.to_string())?;
+
+        let effects = value
+            .field("effects")?
+            .as_array()?
+            .iter()
+            .map(Effect::from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            cycle: value.field("cycle")?.as_number()?,
+            ip: value.field("ip")?.as_number()? as usize,
+            operation,
+            values: value.field("values")?.as_words()?,
+            effects,
+            registers: value.field("regs")?.as_words()?,
+            stack_depth: value.field("sp")?.as_number()? as usize,
+        })
+    }
+}
+
+impl Effect {
+    fn from_json(value: &Json) -> Result<Self, String> {
+        let word =
+            |name: &str| -> Result<Word, String> { Ok(value.field(name)?.as_number()? as Word) };
+
+        if let Ok(index) = value.field("reg") {
+            Ok(Effect::Register {
+                index: index.as_number()? as usize,
+                old: word("old")?,
+                new: word("new")?,
+            })
+        } else if let Ok(address) = value.field("mem") {
+            Ok(Effect::Memory {
+                address: address.as_number()? as usize,
+                old: word("old")?,
+                new: word("new")?,
+            })
+        } else if let Ok(value) = word("push") {
+            Ok(Effect::Push(value))
+        } else if let Ok(value) = word("pop") {
+            Ok(Effect::Pop(value))
+        } else if let Ok(value) = word("in") {
+            Ok(Effect::Input(value))
+        } else {
+            Ok(Effect::Output(word("out")?))
+        }
+    }
+}
+
+/// Just enough JSON to read back the lines `to_json` writes.
+enum Json {
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = JsonParser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+
+        if parser.position == parser.bytes.len() {
+            Ok(value)
+        } else {
+            Err(format!("unexpected data at column {}", parser.position + 1))
+        }
+    }
+
+    fn field(&self, name: &str) -> Result<&Json, String> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| format!("missing field `{}`", name)),
+            _ => Err(format!("expected an object with `{}`", name)),
+        }
+    }
+
+    fn as_number(&self) -> Result<u64, String> {
+        match self {
+            Json::Number(number) => Ok(*number),
+            _ => Err("expected a number".to_owned()),
+        }
+    }
+
+    fn as_str(&self) -> Result<&str, String> {
+        match self {
+            Json::String(string) => Ok(string),
+            _ => Err("expected a string".to_owned()),
+        }
+    }
+
+    fn as_array(&self) -> Result<&[Json], String> {
+        match self {
+            Json::Array(values) => Ok(values),
+            _ => Err("expected an array".to_owned()),
+        }
+    }
+
+    fn as_words(&self) -> Result<Vec<Word>, String> {
+        self.as_array()?
+            .iter()
+            .map(|value| value.as_number().map(|number| number as Word))
+            .collect()
+    }
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+
+        if self.bytes.get(self.position) == Some(&byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "expected `{}` at column {}",
+                byte as char,
+                self.position + 1
+            ))
+        }
+    }
+
+    fn next_is(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        self.bytes.get(self.position) == Some(&byte)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+
+        match self.bytes.get(self.position) {
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields = vec![];
+
+                while !self.next_is(b'}') {
+                    if !fields.is_empty() {
+                        self.expect(b',')?;
+                    }
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                }
+                self.expect(b'}')?;
+
+                Ok(Json::Object(fields))
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut values = vec![];
+
+                while !self.next_is(b']') {
+                    if !values.is_empty() {
+                        self.expect(b',')?;
+                    }
+                    values.push(self.value()?);
+                }
+                self.expect(b']')?;
+
+                Ok(Json::Array(values))
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(byte) if byte.is_ascii_digit() => {
+                let start = self.position;
+                while self
+                    .bytes
+                    .get(self.position)
+                    .is_some_and(u8::is_ascii_digit)
+                {
+                    self.position += 1;
+                }
+
+                let digits = String::from_utf8_lossy(&self.bytes[start..self.position]);
+                digits
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("invalid number `{}`", digits))
+            }
+            _ => Err(format!("unexpected input at column {}", self.position + 1)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let start = self.position;
+
+        while let Some(&byte) = self.bytes.get(self.position) {
+            if byte == b'"' {
+                let string = String::from_utf8_lossy(&self.bytes[start..self.position]);
+                self.position += 1;
+                return Ok(string.into_owned());
+            }
+            self.position += 1;
+        }
+
+        Err("unterminated string".to_owned())
+    }
+}
+
+fn json_array(words: &[Word]) -> String {
+    let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+    format!("[{}]", words.join(","))
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::Register { index, old, new } => write!(f, "#{} {}->{}", index, old, new),
+            Effect::Memory { address, old, new } => write!(f, "[{}] {}->{}", address, old, new),
+            Effect::Push(value) => write!(f, "push {}", value),
+            Effect::Pop(value) => write!(f, "pop {}", value),
+            Effect::Input(value) => write!(f, "in {}", value),
+            Effect::Output(value) => write!(f, "out {}", value),
+        }
+    }
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(writer: W, format: TraceFormat) -> Self {
+        Self {
+            writer: Box::new(writer),
+            format,
+            filter: TraceFilter::default(),
+            symbols: Symbols::new(),
+            error: None,
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    pub fn wants(&self, cycle: u64, ip: usize) -> bool {
+        self.error.is_none() && self.filter.matches(cycle, ip)
+    }
+
+    /// Write failures are kept until `finish` rather than interrupting the VM.
+    pub fn write(&mut self, entry: &TraceEntry) {
+        let line = match self.format {
+            TraceFormat::Text => entry.to_annotated_text(&self.symbols),
+            TraceFormat::Json => entry.to_json(),
+        };
+
+        if let Err(error) = writeln!(self.writer, "{}", line) {
+            self.error = Some(error);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streams::SharedBuffer;
+    use crate::vm::VM;
+
+    fn trace(program: Vec<Word>, format: TraceFormat, filter: TraceFilter) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut vm = VM::new(program);
+        vm.set_tracer(Tracer::new(buffer.clone(), format).with_filter(filter));
+        vm.run();
+        vm.take_tracer().unwrap().finish().unwrap();
+
+        let bytes = buffer.0.borrow();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(|line| line.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn text_trace_test() {
+        let lines = trace(
+            vec![9, 32768, 32769, 88, 2, 32768, 19, 32768, 0],
+            TraceFormat::Text,
+            TraceFilter::default(),
+        );
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "         1     0: add #0=0 #1=0 88 | #0 0->88");
+        assert_eq!(lines[1], "         2     4: push #0=88 | push 88");
+        assert_eq!(lines[2], "         3     6: out #0=88 | out 88");
+    }
+
+    #[test]
+    fn annotated_text_test() {
+        let symbols = Symbols::parse("register 0 total\nfunction 6 done").unwrap();
+        let buffer = SharedBuffer::default();
+        let mut vm = VM::new(vec![9, 32768, 32768, 7, 17, 6, 21, 0]);
+        vm.set_tracer(Tracer::new(buffer.clone(), TraceFormat::Text).with_symbols(symbols));
+        vm.run();
+        vm.take_tracer().unwrap().finish().unwrap();
+
+        let bytes = buffer.0.borrow();
+        let lines: Vec<&str> = std::str::from_utf8(&bytes).unwrap().lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "         1     0: add total=0 total=0 7 | total 0->7",
+                "         2     4: call 6 <done> | push 6",
+                "         3     6 <done>: noop",
+                "         4     7: halt",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_trace_test() {
+        let lines = trace(
+            vec![16, 5, 7, 21, 0, 0],
+            TraceFormat::Json,
+            TraceFilter::default(),
+        );
+
+        assert_eq!(
+            lines[0],
+            "{\"cycle\":1,\"ip\":0,\"op\":\"wmem\",\"args\":[5,7],\"values\":[5,7],\"effects\":[{\"mem\":5,\"old\":0,\"new\":7}],\"regs\":[0,0,0,0,0,0,0,0],\"sp\":0}"
+        );
+    }
+
+    #[test]
+    fn json_round_trip_test() {
+        let lines = trace(
+            vec![2, 4, 3, 32769, 16, 12, 32769, 19, 32769, 0, 0, 0, 0],
+            TraceFormat::Json,
+            TraceFilter::default(),
+        );
+        assert_eq!(lines.len(), 5);
+
+        for line in &lines {
+            let entry = TraceEntry::from_json(line).unwrap();
+            assert_eq!(&entry.to_json(), line);
+        }
+
+        assert!(TraceEntry::from_json("{\"cycle\":1}").is_err());
+        assert!(TraceEntry::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn filter_test() {
+        let filter = TraceFilter {
+            addresses: Some(1..3),
+            cycles: Some(0..3),
+        };
+        let lines = trace(vec![21, 21, 21, 21, 0], TraceFormat::Text, filter);
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("2     1: noop"));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::trace::TraceEntry;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Instructions are at most four words long, so address gaps this small are
+/// treated as straight-line code when summarizing regions.
+const ADDRESS_GAP: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Divergence {
+    /// The traces executed different instructions.
+    Instructions,
+    /// The same instructions ran with different values, registers or stack.
+    State,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub left: Range<usize>,
+    pub right: Range<usize>,
+    pub kind: Divergence,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceDiff {
+    pub first_divergence: Option<(usize, usize)>,
+    pub regions: Vec<Region>,
+    pub matched: usize,
+}
+
+fn same_instruction(left: &TraceEntry, right: &TraceEntry) -> bool {
+    left.ip == right.ip && left.operation == right.operation
+}
+
+fn same_state(left: &TraceEntry, right: &TraceEntry) -> bool {
+    left.values == right.values
+        && left.effects == right.effects
+        && left.registers == right.registers
+        && left.stack_depth == right.stack_depth
+}
+
+/// Walks both traces in lockstep; when the executed instructions differ it
+/// looks up to `window` entries ahead on each side for the nearest common
+/// instruction and records everything skipped as a divergent region. Runs of
+/// matching instructions whose state differs are recorded as regions too.
+pub fn diff(left: &[TraceEntry], right: &[TraceEntry], window: usize) -> TraceDiff {
+    let mut first_divergence = None;
+    let mut regions = vec![];
+    let mut matched = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < left.len() && j < right.len() {
+        if same_instruction(&left[i], &right[j]) {
+            if !same_state(&left[i], &right[j]) {
+                first_divergence.get_or_insert((i, j));
+                match regions.last_mut() {
+                    Some(Region {
+                        left,
+                        right,
+                        kind: Divergence::State,
+                    }) if left.end == i && right.end == j => {
+                        left.end += 1;
+                        right.end += 1;
+                    }
+                    _ => regions.push(Region {
+                        left: i..i + 1,
+                        right: j..j + 1,
+                        kind: Divergence::State,
+                    }),
+                }
+            }
+            matched += 1;
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        first_divergence.get_or_insert((i, j));
+
+        match resynchronize(&left[i..], &right[j..], window) {
+            Some((skip_left, skip_right)) => {
+                regions.push(Region {
+                    left: i..i + skip_left,
+                    right: j..j + skip_right,
+                    kind: Divergence::Instructions,
+                });
+                i += skip_left;
+                j += skip_right;
+            }
+            None => break,
+        }
+    }
+
+    if i < left.len() || j < right.len() {
+        first_divergence.get_or_insert((i, j));
+        regions.push(Region {
+            left: i..left.len(),
+            right: j..right.len(),
+            kind: Divergence::Instructions,
+        });
+    }
+
+    TraceDiff {
+        first_divergence,
+        regions,
+        matched,
+    }
+}
+
+fn resynchronize(
+    left: &[TraceEntry],
+    right: &[TraceEntry],
+    window: usize,
+) -> Option<(usize, usize)> {
+    let mut first_seen: HashMap<usize, usize> = HashMap::new();
+    for (index, entry) in right.iter().take(window).enumerate() {
+        first_seen.entry(entry.ip).or_insert(index);
+    }
+
+    let mut best: Option<(usize, usize)> = None;
+
+    for (skip_left, entry) in left.iter().take(window).enumerate() {
+        if best.is_some_and(|(l, r)| skip_left >= l + r) {
+            break;
+        }
+
+        if let Some(&skip_right) = first_seen.get(&entry.ip) {
+            let better = best.is_none_or(|(l, r)| skip_left + skip_right < l + r);
+            if better && same_instruction(entry, &right[skip_right]) {
+                best = Some((skip_left, skip_right));
+            }
+        }
+    }
+
+    best
+}
+
+/// Collapses the addresses executed by `entries` into sorted ranges.
+pub fn address_ranges(entries: &[TraceEntry]) -> Vec<Range<usize>> {
+    let mut addresses: Vec<usize> = entries.iter().map(|entry| entry.ip).collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for address in addresses {
+        match ranges.last_mut() {
+            Some(range) if address <= range.end + ADDRESS_GAP => range.end = address + 1,
+            _ => ranges.push(address..address + 1),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Operation;
+
+    fn entries(ips: &[usize]) -> Vec<TraceEntry> {
+        ips.iter()
+            .enumerate()
+            .map(|(cycle, &ip)| TraceEntry {
+                cycle: cycle as u64 + 1,
+                ip,
+                operation: Operation::NoOp,
+                values: vec![],
+                effects: vec![],
+                registers: vec![0; 8],
+                stack_depth: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_test() {
+        let trace = entries(&[0, 1, 2, 3]);
+        let result = diff(&trace, &trace, 100);
+
+        assert_eq!(result.first_divergence, None);
+        assert!(result.regions.is_empty());
+        assert_eq!(result.matched, 4);
+    }
+
+    #[test]
+    fn state_divergence_test() {
+        let left = entries(&[0, 1, 2]);
+        let mut right = entries(&[0, 1, 2]);
+        right[1].registers[7] = 25734;
+
+        let result = diff(&left, &right, 100);
+        assert_eq!(result.first_divergence, Some((1, 1)));
+        assert_eq!(
+            result.regions,
+            vec![Region {
+                left: 1..2,
+                right: 1..2,
+                kind: Divergence::State,
+            }]
+        );
+    }
+
+    #[test]
+    fn later_state_divergence_test() {
+        let left = entries(&[0, 1, 10, 2, 3, 4, 5]);
+        let mut right = entries(&[0, 1, 20, 2, 3, 4, 5]);
+        right[4].stack_depth = 1;
+        right[5].stack_depth = 1;
+
+        let result = diff(&left, &right, 100);
+        assert_eq!(result.first_divergence, Some((2, 2)));
+        assert_eq!(
+            result.regions,
+            vec![
+                Region {
+                    left: 2..3,
+                    right: 2..3,
+                    kind: Divergence::Instructions,
+                },
+                Region {
+                    left: 4..6,
+                    right: 4..6,
+                    kind: Divergence::State,
+                },
+            ]
+        );
+        assert_eq!(result.matched, 6);
+    }
+
+    #[test]
+    fn region_test() {
+        let left = entries(&[0, 1, 10, 11, 12, 2, 3]);
+        let right = entries(&[0, 1, 20, 2, 3, 30]);
+
+        let result = diff(&left, &right, 100);
+        assert_eq!(result.first_divergence, Some((2, 2)));
+        assert_eq!(
+            result.regions,
+            vec![
+                Region {
+                    left: 2..5,
+                    right: 2..3,
+                    kind: Divergence::Instructions,
+                },
+                Region {
+                    left: 7..7,
+                    right: 5..6,
+                    kind: Divergence::Instructions,
+                },
+            ]
+        );
+        assert_eq!(result.matched, 4);
+        assert_eq!(address_ranges(&left[2..5]), vec![10..13]);
+    }
+}
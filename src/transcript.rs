@@ -0,0 +1,655 @@
+use crate::assembler::unescape;
+use crate::vm::{HookContext, HookResult, State, Word, VM};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+const HEADER: &str = "synacor-transcript 1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Words printed by `out`, split after each newline.
+    Output(Vec<Word>),
+    /// Words consumed by `in`, split after each newline.
+    Input(Vec<Word>),
+    /// A memory write from outside the program, e.g. a patch.
+    Memory {
+        address: usize,
+        value: Word,
+    },
+    /// A register write from outside the program.
+    Register {
+        index: usize,
+        value: Word,
+    },
+    /// A hook ran for a call to `address`, writing `writes`. Replays play
+    /// the writes back instead of needing the hook.
+    Hook {
+        address: usize,
+        returned: bool,
+        writes: Vec<HookWrite>,
+    },
+    /// Memoization was turned on or off, which changes cycle counts.
+    Memoize(bool),
+    /// A snapshot was restored or execution stepped backwards, so nothing
+    /// after this can be replayed.
+    Rewind,
+    End,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookWrite {
+    Register { index: usize, value: Word },
+    Memory { address: usize, value: Word },
+    Push(Word),
+    Pop,
+}
+
+/// Everything that went in and out of a VM, each event tagged with the cycle
+/// count at which it happened. Stack edits made through `get_stack_mut` are
+/// not seen.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    pub events: Vec<(u64, Event)>,
+}
+
+#[derive(Debug)]
+pub enum TranscriptError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub cycles: u64,
+    pub output: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayError {
+    /// The first output word that differs, with the cycle its line started
+    /// printing at. `None` means that side printed nothing more.
+    Output {
+        position: usize,
+        expected: Option<(u64, Word)>,
+        actual: Option<(u64, Word)>,
+    },
+    /// The VM stopped before reaching a recorded event.
+    Stopped {
+        cycle: u64,
+        expected: u64,
+        state: State,
+    },
+    Rewound {
+        cycle: u64,
+    },
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_output(&mut self, cycle: u64, word: Word) {
+        match self.events.last_mut() {
+            Some((_, Event::Output(words))) if words.last() != Some(&(b'\n' as Word)) => {
+                words.push(word)
+            }
+            _ => self.events.push((cycle, Event::Output(vec![word]))),
+        }
+    }
+
+    pub(crate) fn record_input(&mut self, cycle: u64, word: Word) {
+        match self.events.last_mut() {
+            Some((_, Event::Input(words))) if words.last() != Some(&(b'\n' as Word)) => {
+                words.push(word)
+            }
+            _ => self.events.push((cycle, Event::Input(vec![word]))),
+        }
+    }
+
+    pub(crate) fn record(&mut self, cycle: u64, event: Event) {
+        if event == Event::Rewind && self.events.last().is_some_and(|(_, last)| *last == event) {
+            return;
+        }
+        self.events.push((cycle, event));
+    }
+
+    /// Marks where the session ended, so a replay runs exactly as far.
+    pub fn finish(&mut self, cycle: u64) {
+        self.events.push((cycle, Event::End));
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TranscriptError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TranscriptError> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+
+        for (cycle, event) in &self.events {
+            let line = match event {
+                Event::Output(words) => format!("out {}", escape(words)),
+                Event::Input(words) => format!("in {}", escape(words)),
+                Event::Memory { address, value } => format!("mem {} {}", address, value),
+                Event::Register { index, value } => format!("reg {} {}", index, value),
+                Event::Hook {
+                    address,
+                    returned,
+                    writes,
+                } => {
+                    let result = if *returned { "returned" } else { "declined" };
+                    let writes: Vec<String> = writes.iter().map(|write| write.to_text()).collect();
+                    format!("hook {} {} {}", address, result, writes.join(", "))
+                        .trim_end()
+                        .to_owned()
+                }
+                Event::Memoize(true) => "memoize on".to_owned(),
+                Event::Memoize(false) => "memoize off".to_owned(),
+                Event::Rewind => "rewind".to_owned(),
+                Event::End => "end".to_owned(),
+            };
+            text.push_str(&format!("{} {}\n", cycle, line));
+        }
+
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, TranscriptError> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(TranscriptError::Parse {
+                line: 1,
+                message: format!("expected `{}`", HEADER),
+            });
+        }
+
+        let mut events = vec![];
+        for (index, line) in lines {
+            let event = parse_event(line).map_err(|message| TranscriptError::Parse {
+                line: index + 1,
+                message,
+            })?;
+            events.push(event);
+        }
+
+        Ok(Self { events })
+    }
+
+    fn output(&self) -> Vec<(u64, Word)> {
+        self.events
+            .iter()
+            .flat_map(|(cycle, event)| match event {
+                Event::Output(words) => words.iter().map(|&word| (*cycle, word)).collect(),
+                _ => vec![],
+            })
+            .collect()
+    }
+}
+
+fn parse_event(line: &str) -> Result<(u64, Event), String> {
+    let mut fields = line.splitn(3, ' ');
+    let cycle = fields
+        .next()
+        .and_then(|cycle| cycle.parse::<u64>().ok())
+        .ok_or_else(|| format!("`{}` doesn't start with a cycle count", line))?;
+    let kind = fields.next().unwrap_or("");
+    let rest = fields.next().unwrap_or("");
+    let numbers = || -> Result<(usize, Word), String> {
+        let mut numbers = rest.split(' ').map(|number| number.parse::<usize>().ok());
+        match (numbers.next(), numbers.next(), numbers.next()) {
+            (Some(Some(first)), Some(Some(second)), None) if second < 1 << 16 => {
+                Ok((first, second as Word))
+            }
+            _ => Err(format!("`{}` needs two numbers", kind)),
+        }
+    };
+
+    let event = match kind {
+        "out" => Event::Output(unescape(rest)?),
+        "in" => Event::Input(unescape(rest)?),
+        "mem" => {
+            let (address, value) = numbers()?;
+            Event::Memory { address, value }
+        }
+        "reg" => {
+            let (index, value) = numbers()?;
+            Event::Register { index, value }
+        }
+        "hook" => parse_hook(rest)?,
+        "memoize" => match rest {
+            "on" => Event::Memoize(true),
+            "off" => Event::Memoize(false),
+            _ => return Err(format!("`memoize {}` is neither on nor off", rest)),
+        },
+        "rewind" => Event::Rewind,
+        "end" => Event::End,
+        _ => return Err(format!("unknown event `{}`", kind)),
+    };
+
+    Ok((cycle, event))
+}
+
+fn parse_hook(text: &str) -> Result<Event, String> {
+    let mut fields = text.splitn(3, ' ');
+    let address = fields
+        .next()
+        .and_then(|address| address.parse::<usize>().ok())
+        .ok_or_else(|| format!("`{}` doesn't start with an address", text))?;
+    let returned = match fields.next() {
+        Some("returned") => true,
+        Some("declined") => false,
+        _ => return Err(format!("`{}` needs `returned` or `declined`", text)),
+    };
+    let writes = match fields.next() {
+        Some(writes) => writes
+            .split(", ")
+            .map(HookWrite::parse)
+            .collect::<Result<Vec<HookWrite>, String>>()?,
+        None => vec![],
+    };
+
+    Ok(Event::Hook {
+        address,
+        returned,
+        writes,
+    })
+}
+
+impl HookWrite {
+    fn to_text(self) -> String {
+        match self {
+            HookWrite::Register { index, value } => format!("reg {} {}", index, value),
+            HookWrite::Memory { address, value } => format!("mem {} {}", address, value),
+            HookWrite::Push(value) => format!("push {}", value),
+            HookWrite::Pop => "pop".to_owned(),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = text.split(' ').collect();
+        let number = |field: &str| field.parse::<usize>().ok();
+        let word = |field: &str| field.parse::<Word>().ok();
+
+        let write = match fields[..] {
+            ["reg", index, value] => number(index)
+                .zip(word(value))
+                .map(|(index, value)| HookWrite::Register { index, value }),
+            ["mem", address, value] => number(address)
+                .zip(word(value))
+                .map(|(address, value)| HookWrite::Memory { address, value }),
+            ["push", value] => word(value).map(HookWrite::Push),
+            ["pop"] => Some(HookWrite::Pop),
+            _ => None,
+        };
+        write.ok_or_else(|| format!("`{}` is not a hook write", text))
+    }
+
+    fn apply(self, hooked: &mut HookContext) {
+        match self {
+            HookWrite::Register { index, value } => {
+                hooked.set_register(index, value);
+            }
+            HookWrite::Memory { address, value } => {
+                hooked.set_memory(address, value);
+            }
+            HookWrite::Push(value) => hooked.push(value),
+            HookWrite::Pop => {
+                hooked.pop();
+            }
+        }
+    }
+}
+
+/// Printable ASCII is kept as is; everything else is escaped so any value
+/// survives the round trip through `unescape`.
+fn escape(words: &[Word]) -> String {
+    words
+        .iter()
+        .map(|&word| match word {
+            10 => "\\n".to_owned(),
+            92 => "\\\\".to_owned(),
+            32..=126 => (word as u8 as char).to_string(),
+            _ => format!("\\{{{}}}", word),
+        })
+        .collect()
+}
+
+/// Runs `memory` in a fresh VM with the transcript's input and outside writes
+/// applied at the recorded cycles, and checks it prints exactly the same.
+/// Hooked calls are answered with the writes their hooks made, and
+/// memoization is switched as it was.
+pub fn replay(memory: &[Word], transcript: &Transcript) -> Result<Replay, ReplayError> {
+    let mut vm = VM::new(memory.to_vec());
+    vm.set_transcript(Transcript::new());
+
+    let mut hooked: BTreeMap<usize, VecDeque<(u64, bool, Vec<HookWrite>)>> = BTreeMap::new();
+    for (cycle, event) in &transcript.events {
+        if let Event::Hook {
+            address,
+            returned,
+            writes,
+        } = event
+        {
+            hooked
+                .entry(*address)
+                .or_default()
+                .push_back((*cycle, *returned, writes.clone()));
+        }
+    }
+    for (address, mut calls) in hooked {
+        vm.set_hook(address, move |hooked: &mut HookContext| {
+            if calls.front().map(|(cycle, _, _)| *cycle) != Some(hooked.get_cycles()) {
+                return HookResult::Declined;
+            }
+            let (_, returned, writes) = calls.pop_front().unwrap();
+            for write in writes {
+                write.apply(hooked);
+            }
+            if returned {
+                HookResult::Returned
+            } else {
+                HookResult::Declined
+            }
+        });
+    }
+
+    for (_, event) in &transcript.events {
+        if let Event::Input(words) = event {
+            for &word in words {
+                vm.add_input(word);
+            }
+        }
+    }
+
+    let mut end = 0;
+    for (cycle, event) in &transcript.events {
+        match *event {
+            Event::Memory { address, value } => {
+                advance(&mut vm, *cycle, transcript)?;
+                vm.set_memory(address, value);
+            }
+            Event::Register { index, value } => {
+                advance(&mut vm, *cycle, transcript)?;
+                vm.set_register(index, value);
+            }
+            Event::Memoize(enabled) => {
+                advance(&mut vm, *cycle, transcript)?;
+                if enabled {
+                    vm.enable_memoization();
+                } else {
+                    vm.disable_memoization();
+                }
+            }
+            Event::Rewind => return Err(ReplayError::Rewound { cycle: *cycle }),
+            _ => {}
+        }
+        end = *cycle;
+    }
+
+    advance(&mut vm, end, transcript)?;
+    compare(&vm, transcript)?;
+
+    Ok(Replay {
+        cycles: vm.get_cycles(),
+        output: transcript.output().len(),
+    })
+}
+
+fn advance(vm: &mut VM, cycle: u64, transcript: &Transcript) -> Result<(), ReplayError> {
+    if vm.get_cycles() < cycle {
+        vm.run_for(cycle - vm.get_cycles());
+    }
+
+    if vm.get_cycles() != cycle {
+        compare(vm, transcript)?;
+        return Err(ReplayError::Stopped {
+            cycle: vm.get_cycles(),
+            expected: cycle,
+            state: vm.get_state(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks the output so far, which may stop short of the expected output
+/// until the replay has reached the end of the transcript.
+fn compare(vm: &VM, transcript: &Transcript) -> Result<(), ReplayError> {
+    let expected = transcript.output();
+    let actual = vm
+        .get_transcript()
+        .map(Transcript::output)
+        .unwrap_or_default();
+    let finished = vm.get_cycles() >= last_cycle(transcript);
+
+    let position = expected
+        .iter()
+        .zip(&actual)
+        .position(|((_, expected), (_, actual))| expected != actual)
+        .or(
+            if actual.len() > expected.len() || (finished && actual.len() < expected.len()) {
+                Some(expected.len().min(actual.len()))
+            } else {
+                None
+            },
+        );
+
+    match position {
+        Some(position) => Err(ReplayError::Output {
+            position,
+            expected: expected.get(position).copied(),
+            actual: actual.get(position).copied(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn last_cycle(transcript: &Transcript) -> u64 {
+    transcript.events.last().map_or(0, |(cycle, _)| *cycle)
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranscriptError::Io(error) => write!(f, "{}", error),
+            TranscriptError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptError {}
+
+impl From<io::Error> for TranscriptError {
+    fn from(error: io::Error) -> Self {
+        TranscriptError::Io(error)
+    }
+}
+
+impl From<TranscriptError> for io::Error {
+    fn from(error: TranscriptError) -> Self {
+        match error {
+            TranscriptError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |word: &Option<(u64, Word)>| match word {
+            Some((cycle, word)) => {
+                format!("`{}` in the line from cycle {}", escape(&[*word]), cycle)
+            }
+            None => "nothing".to_owned(),
+        };
+
+        match self {
+            ReplayError::Output {
+                position,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "output differs at word {}: expected {}, got {}",
+                position,
+                describe(expected),
+                describe(actual)
+            ),
+            ReplayError::Stopped {
+                cycle,
+                expected,
+                state,
+            } => write!(
+                f,
+                "the VM stopped ({:?}) at cycle {} before reaching cycle {}",
+                state, cycle, expected
+            ),
+            ReplayError::Rewound { cycle } => write!(
+                f,
+                "the session rewound at cycle {}, so it can't be replayed past it",
+                cycle
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    /// Echoes each character it reads until it reads a `q`.
+    fn echo() -> Vec<Word> {
+        let source = "
+            loop: in(#0)
+            out(#0)
+            eq(#1, #0, 'q')
+            jf(#1, loop)
+            out(\"bye\\n\")
+            halt
+        ";
+        assemble(source).unwrap().words
+    }
+
+    fn record(memory: &[Word], input: &str) -> Transcript {
+        let mut vm = VM::new(memory.to_vec());
+        vm.set_transcript(Transcript::new());
+        vm.run();
+        for (index, character) in input.chars().enumerate() {
+            if index == 1 {
+                vm.set_register(7, 99);
+            }
+            vm.add_input(character as Word);
+            vm.run();
+        }
+
+        let mut transcript = vm.take_transcript().unwrap();
+        transcript.finish(vm.get_cycles());
+        transcript
+    }
+
+    #[test]
+    fn record_test() {
+        let transcript = record(&echo(), "a\u{e9}q");
+
+        assert_eq!(
+            transcript.to_text(),
+            "synacor-transcript 1\n1 in a\n2 out a\n4 reg 7 99\n5 in \\{233}\n6 out \\{233}\n\
+             9 in q\n10 out qbye\\n\n17 end\n"
+        );
+        assert_eq!(
+            Transcript::parse(&transcript.to_text()).unwrap(),
+            transcript
+        );
+    }
+
+    #[test]
+    fn replay_test() {
+        let memory = echo();
+        let transcript = record(&memory, "abq");
+
+        assert_eq!(
+            replay(&memory, &transcript),
+            Ok(Replay {
+                cycles: 17,
+                output: 7,
+            })
+        );
+
+        let mut changed = memory.clone();
+        let bye = changed
+            .iter()
+            .position(|&word| word == b'y' as Word)
+            .unwrap();
+        changed[bye] = b'Y' as Word;
+        assert_eq!(
+            replay(&changed, &transcript),
+            Err(ReplayError::Output {
+                position: 4,
+                expected: Some((10, b'y' as Word)),
+                actual: Some((10, b'Y' as Word)),
+            })
+        );
+
+        let mut rewound = transcript.clone();
+        rewound.events.insert(1, (2, Event::Rewind));
+        assert_eq!(
+            replay(&memory, &rewound),
+            Err(ReplayError::Rewound { cycle: 2 })
+        );
+    }
+
+    #[test]
+    fn hook_replay_test() {
+        let source = "
+            loop: in(#0)
+            call(next)
+            out(#0)
+            jmp(loop)
+            next: add(#0, #0, 1)
+            ret
+        ";
+        let assembly = assemble(source).unwrap();
+        let next = assembly.labels["next"];
+        let mut vm = VM::new(assembly.words.clone());
+        vm.set_transcript(Transcript::new());
+        vm.enable_memoization();
+
+        for (index, character) in "aaa".chars().enumerate() {
+            if index == 2 {
+                vm.set_hook(next, |hooked: &mut HookContext| {
+                    let value = hooked.get_registers()[0];
+                    hooked.set_register(0, value + 2);
+                    HookResult::Returned
+                });
+            }
+            vm.add_input(character as Word);
+            vm.run();
+        }
+        assert_eq!(vm.get_output(), "bbc");
+
+        let mut transcript = vm.take_transcript().unwrap();
+        transcript.finish(vm.get_cycles());
+        let text = transcript.to_text();
+        assert!(text.contains("\n0 memoize on\n"));
+        assert!(text.contains(" hook 8 returned reg 0 99\n"));
+        assert_eq!(Transcript::parse(&text).unwrap(), transcript);
+
+        assert_eq!(
+            replay(&assembly.words, &transcript),
+            Ok(Replay {
+                cycles: vm.get_cycles(),
+                output: 3,
+            })
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::vm::Word;
+use std::collections::VecDeque;
+
+/// A single side effect of an instruction, holding what is needed to revert it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Register(usize, Word),
+    Memory(usize, Word),
+    Pushed,
+    Popped(Word),
+    Input(Word),
+    Output(Word),
+}
+
+/// Changes are kept in one flat queue with a per-instruction count alongside,
+/// so recording doesn't allocate for every executed instruction.
+#[derive(Debug)]
+pub struct UndoLog {
+    limit: usize,
+    records: VecDeque<(usize, usize)>,
+    changes: VecDeque<Change>,
+    pending: usize,
+}
+
+impl UndoLog {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            records: VecDeque::new(),
+            changes: VecDeque::new(),
+            pending: 0,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, change: Change) {
+        self.changes.push_back(change);
+        self.pending += 1;
+    }
+
+    pub(crate) fn commit(&mut self, ip: usize) {
+        self.records.push_back((ip, self.pending));
+        self.pending = 0;
+
+        while self.records.len() > self.limit {
+            if let Some((_, count)) = self.records.pop_front() {
+                self.changes.drain(..count);
+            }
+        }
+    }
+
+    pub(crate) fn last_changes(&self) -> Option<impl Iterator<Item = &Change>> {
+        self.records
+            .back()
+            .map(|&(_, count)| self.changes.iter().skip(self.changes.len() - count))
+    }
+
+    /// Removes the most recent instruction, returning its IP and its changes
+    /// newest first, ready to be reverted in order.
+    pub(crate) fn pop(&mut self) -> Option<(usize, Vec<Change>)> {
+        let (ip, count) = self.records.pop_back()?;
+        let changes = self
+            .changes
+            .drain(self.changes.len() - count..)
+            .rev()
+            .collect();
+
+        Some((ip, changes))
+    }
+}
@@ -0,0 +1,476 @@
+use crate::vm::{State, Word, VM};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+
+/// The orb shatters unless its weight stays within this range.
+const WEIGHTS: std::ops::Range<i64> = 1..32_768;
+
+const ANTECHAMBER: &str = "Vault Antechamber";
+const LOCK: &str = "Vault Lock";
+const DOOR: &str = "Vault Door";
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cell {
+    Number(i64),
+    Operator(Operator),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    North,
+    South,
+    East,
+    West,
+}
+
+/// The vault's grid of rooms, with row 0 to the north. Walking into an
+/// operator room and then a number room applies the operator to the orb.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vault {
+    pub grid: Vec<Vec<Cell>>,
+    /// The antechamber, which the orb can't be carried back into.
+    pub start: (usize, usize),
+    /// The vault door, where the walk ends whatever the weight.
+    pub goal: (usize, usize),
+    pub start_weight: i64,
+    pub target: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VaultError {
+    Parse { line: usize, message: String },
+    OutsideGrid { position: (usize, usize) },
+    NoPath,
+    Explore { message: String },
+}
+
+/// Where the orb is, its weight and the operator waiting for a number.
+type Walk = ((usize, usize), i64, Option<Operator>);
+
+/// A room as the game describes it after `== Title ==`.
+#[derive(Clone, Debug, PartialEq)]
+struct Room {
+    title: String,
+    lines: Vec<String>,
+    exits: Vec<Direction>,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::South,
+        Direction::East,
+        Direction::West,
+    ];
+
+    /// The game command that walks this way.
+    pub fn command(self) -> &'static str {
+        match self {
+            Direction::North => "north",
+            Direction::South => "south",
+            Direction::East => "east",
+            Direction::West => "west",
+        }
+    }
+
+    pub fn from_command(command: &str) -> Option<Direction> {
+        Direction::ALL
+            .iter()
+            .copied()
+            .find(|direction| direction.command() == command)
+    }
+
+    fn offset(self, (row, column): (isize, isize)) -> (isize, isize) {
+        match self {
+            Direction::North => (row - 1, column),
+            Direction::South => (row + 1, column),
+            Direction::East => (row, column + 1),
+            Direction::West => (row, column - 1),
+        }
+    }
+}
+
+impl Operator {
+    fn apply(self, weight: i64, number: i64) -> i64 {
+        match self {
+            Operator::Add => weight + number,
+            Operator::Subtract => weight - number,
+            Operator::Multiply => weight * number,
+        }
+    }
+}
+
+/// Reads a grid with one row per line, e.g. `* 8 - 1`.
+pub fn parse_grid(text: &str) -> Result<Vec<Vec<Cell>>, VaultError> {
+    let mut grid: Vec<Vec<Cell>> = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| VaultError::Parse {
+            line: index + 1,
+            message,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row = line
+            .split_whitespace()
+            .map(|token| {
+                parse_cell(token)
+                    .ok_or_else(|| error(format!("`{}` is not a number or operator", token)))
+            })
+            .collect::<Result<Vec<Cell>, VaultError>>()?;
+
+        if grid.first().is_some_and(|first| first.len() != row.len()) {
+            return Err(error(format!(
+                "expected {} rooms but found {}",
+                grid[0].len(),
+                row.len()
+            )));
+        }
+        grid.push(row);
+    }
+
+    Ok(grid)
+}
+
+fn parse_cell(token: &str) -> Option<Cell> {
+    match token {
+        "+" => Some(Cell::Operator(Operator::Add)),
+        "-" => Some(Cell::Operator(Operator::Subtract)),
+        "*" => Some(Cell::Operator(Operator::Multiply)),
+        _ => token.parse().ok().map(Cell::Number),
+    }
+}
+
+/// Maps the vault by walking a copy of `vm`, which must be waiting for a
+/// command in the antechamber, through every room of the grid. Each room's
+/// number or operator is read off its floor, the antechamber's pedestal gives
+/// the orb's starting weight and the vault door gives the target.
+pub fn explore(vm: &VM) -> Result<Vault, VaultError> {
+    let mut fork = VM::from_snapshot(vm.snapshot());
+    let antechamber = parse_room(&send(&mut fork, "look")?)?;
+    if antechamber.title != ANTECHAMBER {
+        return Err(explore_error(format!(
+            "expected to start in the {} but found the {}",
+            ANTECHAMBER, antechamber.title
+        )));
+    }
+
+    let mut rooms: BTreeMap<(isize, isize), Room> = BTreeMap::new();
+    let mut outside: HashSet<(isize, isize)> = HashSet::new();
+    let mut unexplored = VecDeque::new();
+    rooms.insert((0, 0), antechamber);
+    unexplored.push_back(((0, 0), fork.snapshot()));
+
+    while let Some((position, snapshot)) = unexplored.pop_front() {
+        for direction in rooms[&position].exits.clone() {
+            let next = direction.offset(position);
+            if rooms.contains_key(&next) || outside.contains(&next) {
+                continue;
+            }
+
+            let mut fork = VM::from_snapshot(snapshot.clone());
+            let room = parse_room(&send(&mut fork, direction.command())?)?;
+            if room.title == LOCK || room.title == DOOR {
+                rooms.insert(next, room);
+                unexplored.push_back((next, fork.snapshot()));
+            } else {
+                outside.insert(next);
+            }
+        }
+    }
+
+    let top = rooms.keys().map(|&(row, _)| row).min().unwrap();
+    let left = rooms.keys().map(|&(_, column)| column).min().unwrap();
+    let bottom = rooms.keys().map(|&(row, _)| row).max().unwrap();
+    let right = rooms.keys().map(|&(_, column)| column).max().unwrap();
+    let index = |(row, column): (isize, isize)| ((row - top) as usize, (column - left) as usize);
+
+    let mut grid = vec![];
+    for row in top..=bottom {
+        let cells = (left..=right)
+            .map(|column| {
+                let room = rooms.get(&(row, column)).ok_or_else(|| {
+                    let (row, column) = index((row, column));
+                    explore_error(format!("no room at row {} column {}", row, column))
+                })?;
+                let cell = match room.title.as_str() {
+                    ANTECHAMBER => room.quoted("pedestal"),
+                    _ => room.quoted("floor"),
+                };
+                cell.and_then(parse_cell).ok_or_else(|| {
+                    explore_error(format!("unable to read the {} floor", room.title))
+                })
+            })
+            .collect::<Result<Vec<Cell>, VaultError>>()?;
+        grid.push(cells);
+    }
+
+    let (&goal, door) = rooms
+        .iter()
+        .find(|(_, room)| room.title == DOOR)
+        .ok_or_else(|| explore_error(format!("no {} found", DOOR)))?;
+    let number = |text: Option<&str>, what: &str| {
+        text.and_then(|text| text.parse().ok())
+            .ok_or_else(|| explore_error(format!("unable to read the {}", what)))
+    };
+
+    Ok(Vault {
+        start_weight: number(rooms[&(0, 0)].quoted("pedestal"), "orb's weight")?,
+        target: number(door.quoted("carved"), "target weight")?,
+        start: index((0, 0)),
+        goal: index(goal),
+        grid,
+    })
+}
+
+/// Types a command and returns the game's reply.
+fn send(vm: &mut VM, command: &str) -> Result<String, VaultError> {
+    for &byte in command.as_bytes() {
+        vm.add_input(byte as Word);
+    }
+    vm.add_input(b'\n' as Word);
+
+    match vm.run() {
+        State::WaitingForInput => Ok(vm.get_output()),
+        state => Err(explore_error(format!(
+            "the game stopped ({:?}) after `{}`",
+            state, command
+        ))),
+    }
+}
+
+fn parse_room(reply: &str) -> Result<Room, VaultError> {
+    let start = reply
+        .rfind("== ")
+        .ok_or_else(|| explore_error(format!("no room in `{}`", reply.trim())))?;
+    let mut lines = reply[start..].lines();
+    let title = lines.next().unwrap().trim_matches(|c| c == '=' || c == ' ');
+
+    let mut room = Room {
+        title: title.to_owned(),
+        lines: vec![],
+        exits: vec![],
+    };
+    let mut listing_exits = false;
+    for line in lines {
+        if line.starts_with("There ") && line.ends_with(':') {
+            listing_exits = line.contains(" exit");
+        } else if let Some(item) = line.strip_prefix("- ") {
+            if listing_exits {
+                // Exits like `vault` lead out of the grid.
+                room.exits.extend(Direction::from_command(item));
+            }
+        } else {
+            listing_exits = false;
+            room.lines.push(line.to_owned());
+        }
+    }
+
+    Ok(room)
+}
+
+impl Room {
+    /// The first quoted text on the line mentioning `word`.
+    fn quoted(&self, word: &str) -> Option<&str> {
+        let line = self.lines.iter().find(|line| line.contains(word))?;
+        let mut parts = line.split('\'');
+        parts.next()?;
+        parts.next()
+    }
+}
+
+fn explore_error(message: String) -> VaultError {
+    VaultError::Explore { message }
+}
+
+impl Vault {
+    /// Finds the fewest moves that carry the orb from the antechamber to the
+    /// vault door at exactly the target weight, by breadth-first search.
+    pub fn solve(&self) -> Result<Vec<Direction>, VaultError> {
+        for &position in &[self.start, self.goal] {
+            if self.cell(position).is_none() {
+                return Err(VaultError::OutsideGrid { position });
+            }
+        }
+
+        let start: Walk = (self.start, self.start_weight, None);
+        let mut paths: VecDeque<(Walk, Vec<Direction>)> = VecDeque::new();
+        let mut seen: HashSet<Walk> = HashSet::new();
+        paths.push_back((start, vec![]));
+        seen.insert(start);
+
+        while let Some(((position, weight, pending), path)) = paths.pop_front() {
+            for &direction in &Direction::ALL {
+                let next = match self.step(position, direction) {
+                    Some(next) if next != self.start => next,
+                    _ => continue,
+                };
+
+                let (weight, pending) = match (self.cell(next), pending) {
+                    (Some(Cell::Operator(operator)), _) => (weight, Some(operator)),
+                    (Some(Cell::Number(number)), Some(operator)) => {
+                        (operator.apply(weight, number), None)
+                    }
+                    (Some(Cell::Number(_)), None) => (weight, None),
+                    (None, _) => continue,
+                };
+                if !WEIGHTS.contains(&weight) {
+                    continue;
+                }
+
+                let mut path = path.clone();
+                path.push(direction);
+                if next == self.goal {
+                    if weight == self.target {
+                        return Ok(path);
+                    }
+                    continue;
+                }
+
+                let state = (next, weight, pending);
+                if seen.insert(state) {
+                    paths.push_back((state, path));
+                }
+            }
+        }
+
+        Err(VaultError::NoPath)
+    }
+
+    fn cell(&self, (row, column): (usize, usize)) -> Option<Cell> {
+        self.grid.get(row)?.get(column).copied()
+    }
+
+    fn step(&self, (row, column): (usize, usize), direction: Direction) -> Option<(usize, usize)> {
+        let next = match direction {
+            Direction::North => (row.checked_sub(1)?, column),
+            Direction::South => (row + 1, column),
+            Direction::East => (row, column + 1),
+            Direction::West => (row, column.checked_sub(1)?),
+        };
+
+        self.cell(next).map(|_| next)
+    }
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VaultError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            VaultError::OutsideGrid { position } => {
+                write!(f, "{:?} is outside the grid", position)
+            }
+            VaultError::NoPath => write!(f, "no walk reaches the target weight"),
+            VaultError::Explore { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for VaultError {}
+
+impl From<VaultError> for io::Error {
+    fn from(error: VaultError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Program, Strictness};
+    use crate::script::Script;
+    use Direction::*;
+
+    /// The grid challenge.bin's vault has.
+    const CHALLENGE: &str = "* 8 - 1\n4 * 11 *\n+ 4 - 18\n22 - 9 *";
+
+    fn vault(grid: &str, start_weight: i64, target: i64) -> Vault {
+        let grid = parse_grid(grid).unwrap();
+        Vault {
+            start: (grid.len() - 1, 0),
+            goal: (0, grid[0].len() - 1),
+            grid,
+            start_weight,
+            target,
+        }
+    }
+
+    #[test]
+    fn solve_test() {
+        let challenge = vault(CHALLENGE, 22, 30);
+        assert_eq!(
+            challenge.solve(),
+            Ok(vec![
+                North, East, East, North, West, South, East, East, West, North, North, East
+            ])
+        );
+
+        // Reaching 5 would mean walking back through the antechamber.
+        let small = vault("+ 1\n3 +", 3, 4);
+        assert_eq!(small.solve(), Ok(vec![North, East]));
+        assert_eq!(vault("+ 1\n3 +", 3, 5).solve(), Err(VaultError::NoPath));
+
+        assert_eq!(
+            parse_grid("1 +\n2"),
+            Err(VaultError::Parse {
+                line: 2,
+                message: "expected 2 rooms but found 1".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_room_test() {
+        let reply = "As you approach the vault door, the number on the vault door flashes \
+                     black.  The orb evaporates out of your hands.\n\n\
+                     == Vault Door ==\n\
+                     You stand before the door to the vault; it has a large '30' carved into it.\n\n\
+                     The floor of this room is a large mosaic depicting the number '1'.\n\n\
+                     There are 3 exits:\n- south\n- west\n- vault\n\nWhat do you do?\n";
+        let room = parse_room(reply).unwrap();
+
+        assert_eq!(room.title, DOOR);
+        assert_eq!(room.exits, vec![South, West]);
+        assert_eq!(room.quoted("carved"), Some("30"));
+        assert_eq!(
+            room.quoted("floor").and_then(parse_cell),
+            Some(Cell::Number(1))
+        );
+
+        let antechamber = parse_room(
+            "== Vault Antechamber ==\nYou notice the number '22' is carved into the orb's \
+             pedestal.\n\nThings of interest here:\n- orb\n\nThere is 1 exit:\n- north\n",
+        )
+        .unwrap();
+        assert_eq!(antechamber.exits, vec![North]);
+        assert_eq!(antechamber.quoted("pedestal"), Some("22"));
+    }
+
+    #[test]
+    fn explore_test() {
+        // Play the walkthrough as far as the antechamber, with the known
+        // teleporter value rather than searching for it.
+        let walkthrough = include_str!("../challenge.walkthrough")
+            .replace("!solve-teleporter", "!set-register 7 25734");
+        let (to_antechamber, _) = walkthrough.split_once("!solve-vault").unwrap();
+        let program =
+            Program::from_bytes(include_bytes!("../challenge.bin"), Strictness::Lenient).unwrap();
+        let mut vm = VM::new(program.into_words());
+        Script::parse(to_antechamber)
+            .unwrap()
+            .run(&mut vm, &mut |_| {})
+            .unwrap();
+
+        assert_eq!(explore(&vm), Ok(vault(CHALLENGE, 22, 30)));
+    }
+}
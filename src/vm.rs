@@ -1,20 +1,71 @@
-use std::collections::VecDeque;
+use crate::decoder::{decode, DecodeError};
+use crate::memo::Memoizer;
+use crate::snapshot::Snapshot;
+use crate::streams::{
+    InputSource, MemoryInput, MemoryOutput, OutputDecoding, OutputError, OutputSink,
+};
+use crate::trace::{Effect, TraceEntry, Tracer};
+use crate::transcript::{Event, HookWrite, Transcript};
+use crate::undo::{Change, UndoLog};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
 
-const MOD: u16 = 32_768;
-const MAX_CYCLES: u32 = 10_000_000;
+pub(crate) const MOD: u16 = 32_768;
+const DEFAULT_CYCLE_BUDGET: u64 = 10_000_000;
+pub(crate) const REGISTERS: usize = 8;
+/// Registers hold values below this.
+pub const REGISTER_LIMIT: Word = MOD;
+/// Memory and the stack may also hold register references, up to `#7`.
+pub const WORD_LIMIT: Word = MOD + REGISTERS as Word;
+pub const MNEMONICS: [&str; 22] = [
+    "halt", "setr", "push", "pop", "eq", "gt", "jmp", "jit", "jif", "add", "mul", "mod", "and",
+    "or", "not", "rmem", "wmem", "call", "ret", "out", "in", "noop",
+];
 pub type Word = u16;
 
+/// A Rust stand-in for the subroutine at some address. See `VM::set_hook`.
+pub type Hook = Box<dyn FnMut(&mut HookContext) -> HookResult>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookResult {
+    /// The hook did the subroutine's work, so execution carries on after the
+    /// call as if it had returned.
+    Returned,
+    /// The call goes ahead as normal.
+    Declined,
+}
+
+/// What a hook may touch. Changes go through the same paths as instructions,
+/// so they are traced and can be undone.
+pub struct HookContext<'a> {
+    vm: &'a mut VM,
+}
+
+#[derive(Default)]
+struct Hooks(BTreeMap<usize, Hook>);
+
 #[derive(Debug)]
 pub struct VM {
     state: State,
-    cycles: u32,
+    cycles: u64,
+    cycle_budget: Option<u64>,
     registers: Vec<Word>,
     stack: Vec<Word>,
     memory: Vec<Word>,
     ip: usize,
-    input: VecDeque<Word>,
-    output: Vec<char>,
+    input: Box<dyn InputSource>,
+    output: Box<dyn OutputSink>,
     debug: bool,
+    undo: Option<UndoLog>,
+    tracer: Option<Tracer>,
+    tracing: bool,
+    traced_changes: Vec<Change>,
+    transcript: Option<Transcript>,
+    hooks: Hooks,
+    /// What the running hook has written, for the transcript.
+    hook_writes: Vec<HookWrite>,
+    memo: Option<Memoizer>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -22,11 +73,23 @@ pub enum State {
     Initialized,
     Running,
     WaitingForInput,
-    Errored(String),
+    Paused,
+    Errored(VmError),
     Halted,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmError {
+    InvalidRegister { ip: usize, word: Word },
+    InvalidOpcode { ip: usize, opcode: Word },
+    IpOutOfBounds { ip: usize, address: usize },
+    AddressOutOfBounds { ip: usize, address: Word },
+    DivisionByZero { ip: usize, dividend: Word },
+    WriteToLiteral { ip: usize, literal: Word },
+    EmptyStack { ip: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Operation {
     Halt,
     SetRegister(Param, Param),
@@ -52,36 +115,193 @@ pub enum Operation {
     NoOp,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Param {
     Literal(Word),
     Register(usize),
 }
 
+impl Operation {
+    pub fn opcode(&self) -> Word {
+        match self {
+            Operation::Halt => 0,
+            Operation::SetRegister(..) => 1,
+            Operation::Push(..) => 2,
+            Operation::Pop(..) => 3,
+            Operation::Equal(..) => 4,
+            Operation::GreaterThan(..) => 5,
+            Operation::Jump(..) => 6,
+            Operation::JumpIfTrue(..) => 7,
+            Operation::JumpIfFalse(..) => 8,
+            Operation::Add(..) => 9,
+            Operation::Mult(..) => 10,
+            Operation::Mod(..) => 11,
+            Operation::And(..) => 12,
+            Operation::Or(..) => 13,
+            Operation::Not(..) => 14,
+            Operation::ReadMemory(..) => 15,
+            Operation::WriteMemory(..) => 16,
+            Operation::Call(..) => 17,
+            Operation::Return => 18,
+            Operation::Out(..) => 19,
+            Operation::In(..) => 20,
+            Operation::NoOp => 21,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        MNEMONICS[self.opcode() as usize]
+    }
+
+    /// The operand holding the jump or call destination, if any.
+    pub fn target(&self) -> Option<Param> {
+        match *self {
+            Operation::Jump(to) | Operation::Call(to) => Some(to),
+            Operation::JumpIfTrue(_, to) | Operation::JumpIfFalse(_, to) => Some(to),
+            _ => None,
+        }
+    }
+
+    /// The register operand the instruction writes, if any.
+    pub fn destination(&self) -> Option<Param> {
+        match *self {
+            Operation::SetRegister(a, _)
+            | Operation::Pop(a)
+            | Operation::Equal(a, _, _)
+            | Operation::GreaterThan(a, _, _)
+            | Operation::Add(a, _, _)
+            | Operation::Mult(a, _, _)
+            | Operation::Mod(a, _, _)
+            | Operation::And(a, _, _)
+            | Operation::Or(a, _, _)
+            | Operation::Not(a, _)
+            | Operation::ReadMemory(a, _)
+            | Operation::In(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Whether execution can continue with the instruction that follows.
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Operation::Halt | Operation::Jump(_) | Operation::Return
+        )
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        match *self {
+            Operation::Halt | Operation::Return | Operation::NoOp => vec![],
+            Operation::Push(a)
+            | Operation::Pop(a)
+            | Operation::Jump(a)
+            | Operation::Call(a)
+            | Operation::Out(a)
+            | Operation::In(a) => vec![a],
+            Operation::SetRegister(a, b)
+            | Operation::JumpIfTrue(a, b)
+            | Operation::JumpIfFalse(a, b)
+            | Operation::Not(a, b)
+            | Operation::ReadMemory(a, b)
+            | Operation::WriteMemory(a, b) => vec![a, b],
+            Operation::Equal(a, b, c)
+            | Operation::GreaterThan(a, b, c)
+            | Operation::Add(a, b, c)
+            | Operation::Mult(a, b, c)
+            | Operation::Mod(a, b, c)
+            | Operation::And(a, b, c)
+            | Operation::Or(a, b, c) => vec![a, b, c],
+        }
+    }
+}
+
 impl VM {
-    pub fn new(memory: Vec<Word>) -> Self {
+    pub fn new<M: Into<Vec<Word>>>(memory: M) -> Self {
         Self {
             state: State::Initialized,
             cycles: 0,
-            registers: vec![0; 8],
+            cycle_budget: Some(DEFAULT_CYCLE_BUDGET),
+            registers: vec![0; REGISTERS],
             stack: Vec::new(),
-            memory: memory,
+            memory: memory.into(),
             ip: 0,
-            input: VecDeque::new(),
-            output: vec![],
+            input: Box::new(MemoryInput::new()),
+            output: Box::new(MemoryOutput::new()),
             debug: false,
+            undo: None,
+            tracer: None,
+            tracing: false,
+            traced_changes: vec![],
+            transcript: None,
+            hooks: Hooks::default(),
+            hook_writes: vec![],
+            memo: None,
         }
     }
 
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let mut vm = Self::new(vec![]);
+        vm.restore(snapshot);
+        vm
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.clone(),
+            cycles: self.cycles,
+            ip: self.ip,
+            registers: self.registers.clone(),
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+            input: self.input.pending(),
+            output: self.output.pending(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        if let Some(log) = self.undo.as_mut() {
+            *log = UndoLog::new(log.limit());
+        }
+        if let Some(memo) = self.memo.as_mut() {
+            memo.reset();
+        }
+        self.record_event(Event::Rewind);
+        self.state = snapshot.state;
+        self.cycles = snapshot.cycles;
+        self.ip = snapshot.ip;
+        self.registers = snapshot.registers;
+        self.registers.resize(REGISTERS, 0);
+        self.stack = snapshot.stack;
+        self.memory = snapshot.memory;
+        self.input.set_pending(snapshot.input);
+        self.output.set_pending(snapshot.output);
+    }
+
     pub fn add_input(&mut self, value: Word) {
-        self.input.push_back(value);
+        self.input.push(value);
+    }
+
+    /// Replaces where `in` reads from, carrying over any queued input.
+    pub fn set_input<I: InputSource + 'static>(&mut self, mut input: I) {
+        input.set_pending(self.input.pending());
+        self.input = Box::new(input);
+    }
+
+    /// Replaces where `out` writes to, handing it any output not yet taken.
+    pub fn set_output<O: OutputSink + 'static>(&mut self, mut output: O) {
+        output.set_pending(self.output.take());
+        self.output = Box::new(output);
+    }
+
+    pub fn flush_output(&mut self) -> io::Result<()> {
+        self.output.flush()
     }
 
     pub fn get_state(&self) -> State {
         self.state.clone()
     }
 
-    pub fn get_cycles(&self) -> u32 {
+    pub fn get_cycles(&self) -> u64 {
         self.cycles
     }
 
@@ -93,191 +313,506 @@ impl VM {
         self.debug = true
     }
 
-    pub fn fix_teleporter(&mut self) {
-        self.memory[522] = 32768;
-        self.registers[7] = 25734;
-        self.memory[5489] = 21;
-        self.memory[5490] = 21;
-        self.memory[5495] = 7;
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+        if let Some(memo) = self.memo.as_mut() {
+            memo.abandon();
+        }
+    }
+
+    pub fn get_registers(&self) -> &[Word] {
+        &self.registers
+    }
+
+    /// Refuses values of `REGISTER_LIMIT` and up, as well as bad indices.
+    pub fn set_register(&mut self, index: usize, value: Word) -> bool {
+        match self.registers.get_mut(index) {
+            Some(register) if value < REGISTER_LIMIT => {
+                *register = value;
+                if let Some(memo) = self.memo.as_mut() {
+                    memo.taint();
+                }
+                self.record_event(Event::Register { index, value });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get_memory(&self) -> &[Word] {
+        &self.memory
+    }
+
+    /// Refuses values of `WORD_LIMIT` and up, as well as bad addresses.
+    pub fn set_memory(&mut self, address: usize, value: Word) -> bool {
+        match self.memory.get_mut(address) {
+            Some(cell) if value < WORD_LIMIT => {
+                *cell = value;
+                if let Some(memo) = self.memo.as_mut() {
+                    memo.memory_written(address);
+                    memo.taint();
+                }
+                self.record_event(Event::Memory { address, value });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get_stack(&self) -> &[Word] {
+        &self.stack
+    }
+
+    pub fn get_stack_mut(&mut self) -> &mut Vec<Word> {
+        if let Some(memo) = self.memo.as_mut() {
+            memo.abandon();
+        }
+        &mut self.stack
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Starts recording input, output and outside edits into `transcript`.
+    pub fn set_transcript(&mut self, transcript: Transcript) {
+        self.transcript = Some(transcript);
+        if self.memo.is_some() {
+            self.record_event(Event::Memoize(true));
+        }
+    }
+
+    pub fn get_transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    pub fn take_transcript(&mut self) -> Option<Transcript> {
+        self.transcript.take()
+    }
+
+    fn record_event(&mut self, event: Event) {
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.record(self.cycles, event);
+        }
+    }
+
+    /// Runs `hook` instead of any subroutine called at `address`. The call
+    /// still counts as one cycle.
+    pub fn set_hook<F>(&mut self, address: usize, hook: F)
+    where
+        F: FnMut(&mut HookContext) -> HookResult + 'static,
+    {
+        self.hooks.0.insert(address, Box::new(hook));
+    }
+
+    pub fn remove_hook(&mut self, address: usize) -> bool {
+        self.hooks.0.remove(&address).is_some()
+    }
+
+    pub fn get_hooked_addresses(&self) -> Vec<usize> {
+        self.hooks.0.keys().copied().collect()
+    }
+
+    /// Caches the results of subroutine calls that prove to be pure, so later
+    /// calls with the same inputs take one cycle. Cycle counts then differ
+    /// from an unmemoized run.
+    pub fn enable_memoization(&mut self) {
+        self.memo = Some(Memoizer::new());
+        self.record_event(Event::Memoize(true));
     }
 
+    pub fn disable_memoization(&mut self) {
+        self.memo = None;
+        self.record_event(Event::Memoize(false));
+    }
+
+    pub fn get_memoizer(&self) -> Option<&Memoizer> {
+        self.memo.as_ref()
+    }
+
+    /// Records how to revert each executed instruction, keeping at most
+    /// `limit` instructions of history. Edits made through the setters above
+    /// are not recorded.
+    pub fn enable_undo(&mut self, limit: usize) {
+        self.undo = Some(UndoLog::new(limit));
+    }
+
+    pub fn disable_undo(&mut self) {
+        self.undo = None;
+    }
+
+    pub fn get_undo_log(&self) -> Option<&UndoLog> {
+        self.undo.as_ref()
+    }
+
+    /// Reverts the most recently executed instruction, returning false when
+    /// there is no recorded history left. Output still buffered in the sink
+    /// is taken back; output a frontend has already drained stays drained.
+    pub fn step_back(&mut self) -> bool {
+        let (ip, changes) = match self.undo.as_mut().and_then(|log| log.pop()) {
+            Some(record) => record,
+            None => return false,
+        };
+
+        for change in changes {
+            match change {
+                Change::Register(index, value) => self.registers[index] = value,
+                Change::Memory(address, value) => self.memory[address] = value,
+                Change::Pushed => {
+                    self.stack.pop();
+                }
+                Change::Popped(value) => self.stack.push(value),
+                Change::Input(value) => self.input.unread(value),
+                Change::Output(_) => {
+                    self.output.unwrite();
+                }
+            }
+        }
+
+        self.ip = ip;
+        self.cycles -= 1;
+        self.state = State::Paused;
+        if let Some(memo) = self.memo.as_mut() {
+            memo.abandon();
+        }
+        self.record_event(Event::Rewind);
+        true
+    }
+
+    /// Steps back until the instruction at `address` is about to execute.
+    pub fn run_back_to(&mut self, address: usize) -> bool {
+        while self.step_back() {
+            if self.ip == address {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Steps back to just before the most recent instruction that wrote to
+    /// `address`, so stepping forward performs the write again.
+    pub fn run_back_to_write(&mut self, address: usize) -> bool {
+        loop {
+            let wrote = match self.undo.as_ref().and_then(|log| log.last_changes()) {
+                Some(mut changes) => changes.any(|change| match change {
+                    Change::Memory(written, _) => *written == address,
+                    _ => false,
+                }),
+                None => return false,
+            };
+
+            self.step_back();
+
+            if wrote {
+                return true;
+            }
+        }
+    }
+
+    /// Drains the buffered output as text. Anything outside ASCII is replaced
+    /// with U+FFFD; use `decode_output` to choose another policy.
     pub fn get_output(&mut self) -> String {
-        let output_string: String = self.output.iter().collect();
-        self.output = Vec::new();
-        output_string
+        self.get_output_words()
+            .into_iter()
+            .map(|word| match word {
+                0..=127 => word as u8 as char,
+                _ => char::REPLACEMENT_CHARACTER,
+            })
+            .collect()
+    }
+
+    pub fn get_output_words(&mut self) -> Vec<Word> {
+        self.output.take()
+    }
+
+    /// Drains the buffered output as one byte per word, failing on words
+    /// above 255.
+    pub fn get_output_bytes(&mut self) -> Result<Vec<u8>, OutputError> {
+        OutputDecoding::Latin1.encode(&self.output.take())
+    }
+
+    pub fn decode_output(&mut self, decoding: OutputDecoding) -> Result<String, OutputError> {
+        decoding.decode(&self.output.take())
+    }
+
+    pub fn set_cycle_budget(&mut self, budget: Option<u64>) {
+        self.cycle_budget = budget
+    }
+
+    pub fn run(&mut self) -> State {
+        self.run_with(self.cycle_budget, |_| false)
+    }
+
+    pub fn step(&mut self) -> State {
+        self.run_with(Some(1), |_| false)
+    }
+
+    pub fn run_for(&mut self, cycles: u64) -> State {
+        self.run_with(Some(cycles), |_| false)
+    }
+
+    /// Runs until `predicate` holds after an instruction, so resuming from a
+    /// paused position always executes at least one instruction.
+    pub fn run_until<F: FnMut(&VM) -> bool>(&mut self, predicate: F) -> State {
+        self.run_with(self.cycle_budget, predicate)
     }
 
-    pub fn run(&mut self) {
+    fn run_with<F: FnMut(&VM) -> bool>(&mut self, limit: Option<u64>, mut predicate: F) -> State {
+        if let State::Halted | State::Errored(_) = self.state {
+            return self.get_state();
+        }
+
         self.state = State::Running;
+        let mut executed: u64 = 0;
 
-        loop {
-            if self.state != State::Running {
+        while self.state == State::Running {
+            if limit.is_some_and(|limit| executed >= limit) {
+                self.state = State::Paused;
                 break;
             }
 
-            if self.cycles >= MAX_CYCLES {
-                self.error("reached max cycles".to_owned());
-                break;
+            self.execute_next();
+            executed += 1;
+
+            if self.state == State::Running && predicate(self) {
+                self.state = State::Paused;
             }
+        }
+
+        self.get_state()
+    }
+
+    fn execute_next(&mut self) {
+        self.cycles += 1;
+
+        if self.debug {
+            println!("IP: {}", self.ip);
+        }
+
+        let ip = self.ip;
+        let traced = self.start_trace(ip);
+        let result = self
+            .get_next_operation()
+            .and_then(|operation| self.execute(ip, operation));
+
+        if let Err(error) = result {
+            self.error(error);
+        }
+
+        // A blocked `in` runs again once input arrives, so it isn't counted,
+        // traced or recorded; cycle counts then don't depend on how often a
+        // frontend polls.
+        if self.state == State::WaitingForInput {
+            self.cycles -= 1;
+            self.tracing = false;
+            return;
+        }
+
+        if let Some(log) = self.undo.as_mut() {
+            log.commit(ip);
+        }
 
-            self.cycles += 1;
+        if let Some((operation, values)) = traced {
+            self.finish_trace(ip, operation, values);
+        }
+    }
+
+    fn start_trace(&mut self, ip: usize) -> Option<(Operation, Vec<Word>)> {
+        let wanted = self
+            .tracer
+            .as_ref()
+            .is_some_and(|tracer| tracer.wants(self.cycles, ip));
+        let (operation, _) = self.peek_operation().ok().filter(|_| wanted)?;
+        let values = operation
+            .params()
+            .into_iter()
+            .map(|param| self.get(param))
+            .collect();
+        self.tracing = true;
+
+        Some((operation, values))
+    }
+
+    fn finish_trace(&mut self, ip: usize, operation: Operation, values: Vec<Word>) {
+        let changes = std::mem::take(&mut self.traced_changes);
+        let effects = changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Register(index, old) => Effect::Register {
+                    index,
+                    old,
+                    new: self.registers[index],
+                },
+                Change::Memory(address, old) => Effect::Memory {
+                    address,
+                    old,
+                    new: self.memory[address],
+                },
+                Change::Pushed => Effect::Push(self.stack.last().copied().unwrap_or(0)),
+                Change::Popped(value) => Effect::Pop(value),
+                Change::Input(value) => Effect::Input(value),
+                Change::Output(value) => Effect::Output(value),
+            })
+            .collect();
+        let entry = TraceEntry {
+            cycle: self.cycles,
+            ip,
+            operation,
+            values,
+            effects,
+            registers: self.registers.clone(),
+            stack_depth: self.stack.len(),
+        };
+        self.tracing = false;
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.write(&entry);
+        }
+    }
 
-            if self.debug {
-                println!("IP: {}", self.ip);
+    fn execute(&mut self, ip: usize, operation: Operation) -> Result<(), VmError> {
+        if let Some(memo) = self.memo.as_mut() {
+            memo.observe(ip, &operation);
+        }
+
+        match operation {
+            Operation::Halt => self.halt(),
+            Operation::SetRegister(register, value) => self.set(ip, register, self.get(value))?,
+            Operation::Push(value) => self.push(self.get(value)),
+            Operation::Pop(output) => {
+                let value = self.pop().ok_or(VmError::EmptyStack { ip })?;
+                if let Some(memo) = self.memo.as_mut() {
+                    memo.stack_shrunk(self.stack.len());
+                }
+                self.set(ip, output, value)?
+            }
+            Operation::Equal(output, a, b) => {
+                if self.get(a) == self.get(b) {
+                    self.set(ip, output, 1)?
+                } else {
+                    self.set(ip, output, 0)?
+                }
+            }
+            Operation::GreaterThan(output, a, b) => {
+                if self.get(a) > self.get(b) {
+                    self.set(ip, output, 1)?
+                } else {
+                    self.set(ip, output, 0)?
+                }
+            }
+            Operation::Jump(to) => self.jump(to),
+            Operation::JumpIfTrue(condition, to) => {
+                if self.get(condition) > 0 {
+                    self.jump(to)
+                }
             }
+            Operation::JumpIfFalse(condition, to) => {
+                if self.get(condition) == 0 {
+                    self.jump(to)
+                }
+            }
+            Operation::Add(output, a, b) => {
+                self.set(ip, output, (self.get(a).wrapping_add(self.get(b))) % MOD)?
+            }
+            Operation::Mult(output, a, b) => {
+                self.set(ip, output, (self.get(a).wrapping_mul(self.get(b))) % MOD)?
+            }
+            Operation::Mod(output, a, b) => {
+                let dividend = self.get(a);
+                let divisor = self.get(b);
 
-            match self.get_next_operation() {
-                Ok(operation) => match operation {
-                    Operation::Halt => self.halt(),
-                    Operation::SetRegister(register, value) => self.set(register, self.get(value)),
-                    Operation::Push(value) => self.push(self.get(value)),
-                    Operation::Pop(output) => {
-                        if let Some(value) = self.pop() {
-                            self.set(output, value)
-                        } else {
-                            self.error("attempted to pop an empty stack!".to_owned())
-                        }
-                    }
-                    Operation::Equal(output, a, b) => {
-                        if self.get(a) == self.get(b) {
-                            self.set(output, 1)
-                        } else {
-                            self.set(output, 0)
-                        }
-                    }
-                    Operation::GreaterThan(output, a, b) => {
-                        if self.get(a) > self.get(b) {
-                            self.set(output, 1)
-                        } else {
-                            self.set(output, 0)
-                        }
-                    }
-                    Operation::Jump(to) => self.jump(to),
-                    Operation::JumpIfTrue(condition, to) => {
-                        if self.get(condition) > 0 {
-                            self.jump(to)
-                        }
-                    }
-                    Operation::JumpIfFalse(condition, to) => {
-                        if self.get(condition) == 0 {
-                            self.jump(to)
-                        }
-                    }
-                    Operation::Add(output, a, b) => {
-                        self.set(output, (self.get(a).wrapping_add(self.get(b))) % MOD)
-                    }
-                    Operation::Mult(output, a, b) => {
-                        self.set(output, (self.get(a).wrapping_mul(self.get(b))) % MOD)
-                    }
-                    Operation::Mod(output, a, b) => {
-                        self.set(output, (self.get(a) % self.get(b)) % MOD)
-                    }
-                    Operation::And(output, a, b) => self.set(output, self.get(a) & self.get(b)),
-                    Operation::Or(output, a, b) => self.set(output, self.get(a) | self.get(b)),
-                    Operation::Not(output, a) => self.set(output, self.get(a) ^ 0b111111111111111),
-                    Operation::ReadMemory(output, location) => {
-                        self.set(output, self.get_memory(location))
-                    }
-                    Operation::WriteMemory(output, value) => {
-                        self.set_memory(output, self.get(value))
-                    }
-                    Operation::Call(to) => {
-                        self.push(self.ip as u16);
-                        self.jump(to);
+                if divisor == 0 {
+                    return Err(VmError::DivisionByZero { ip, dividend });
+                }
+                self.set(ip, output, (dividend % divisor) % MOD)?
+            }
+            Operation::And(output, a, b) => self.set(ip, output, self.get(a) & self.get(b))?,
+            Operation::Or(output, a, b) => self.set(ip, output, self.get(a) | self.get(b))?,
+            Operation::Not(output, a) => self.set(ip, output, self.get(a) ^ 0b111111111111111)?,
+            Operation::ReadMemory(output, location) => {
+                let value = self.load(ip, location)?;
+                self.set(ip, output, value)?
+            }
+            Operation::WriteMemory(output, value) => self.store(ip, output, self.get(value))?,
+            Operation::Call(to) => {
+                let target = self.get(to) as usize;
+                if self.call_hook(target) == HookResult::Declined
+                    && !self.call_memoized(ip, target)?
+                {
+                    self.push(self.ip as u16);
+                    self.jump(to);
+                    if let Some(memo) = self.memo.as_mut() {
+                        memo.enter(target, &self.registers, self.stack.len(), self.cycles);
                     }
-                    Operation::Return => {
-                        if let Some(value) = self.pop() {
-                            self.jump(Param::Literal(value));
+                }
+            }
+            Operation::Return => {
+                let returning = self
+                    .memo
+                    .as_ref()
+                    .is_some_and(|memo| memo.is_return(self.stack.len()));
+                if let Some(value) = self.pop() {
+                    self.jump(Param::Literal(value));
+                    if let Some(memo) = self.memo.as_mut() {
+                        if returning {
+                            memo.leave(&self.registers, self.cycles);
                         } else {
-                            self.halt();
+                            memo.stack_shrunk(self.stack.len());
                         }
                     }
-                    Operation::Out(value) => self.output.push(self.get(value) as u8 as char),
-                    Operation::In(output) => {
-                        if let Some(value) = self.input.pop_front() {
-                            self.set(output, value);
-                        } else {
-                            self.ip -= 2;
-                            self.state = State::WaitingForInput;
-                        }
+                } else {
+                    self.halt();
+                }
+            }
+            Operation::Out(value) => {
+                let value = self.get(value);
+                self.output.write(value);
+                self.record(Change::Output(value));
+                if let Some(transcript) = self.transcript.as_mut() {
+                    transcript.record_output(self.cycles, value);
+                }
+            }
+            Operation::In(output) => {
+                if let Some(value) = self.input.read() {
+                    self.record(Change::Input(value));
+                    if let Some(transcript) = self.transcript.as_mut() {
+                        transcript.record_input(self.cycles, value);
                     }
-                    Operation::NoOp => {}
-                },
-                Err(message) => self.error(message),
-            }
-        }
-    }
-
-    pub fn get_next_operation(&mut self) -> Result<Operation, String> {
-        let opcode = self.memory[self.ip];
-        self.ip += 1;
-
-        match opcode {
-            0 => Ok(Operation::Halt),
-            1 => Ok(Operation::SetRegister(self.read_param(), self.read_param())),
-            2 => Ok(Operation::Push(self.read_param())),
-            3 => Ok(Operation::Pop(self.read_param())),
-            4 => Ok(Operation::Equal(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            5 => Ok(Operation::GreaterThan(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            6 => Ok(Operation::Jump(self.read_param())),
-            7 => Ok(Operation::JumpIfTrue(self.read_param(), self.read_param())),
-            8 => Ok(Operation::JumpIfFalse(self.read_param(), self.read_param())),
-            9 => Ok(Operation::Add(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            10 => Ok(Operation::Mult(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            11 => Ok(Operation::Mod(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            12 => Ok(Operation::And(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            13 => Ok(Operation::Or(
-                self.read_param(),
-                self.read_param(),
-                self.read_param(),
-            )),
-            14 => Ok(Operation::Not(self.read_param(), self.read_param())),
-            15 => Ok(Operation::ReadMemory(self.read_param(), self.read_param())),
-            16 => Ok(Operation::WriteMemory(self.read_param(), self.read_param())),
-            17 => Ok(Operation::Call(self.read_param())),
-            18 => Ok(Operation::Return),
-            19 => Ok(Operation::Out(self.read_param())),
-            20 => Ok(Operation::In(self.read_param())),
-            21 => Ok(Operation::NoOp),
-            opcode => Err(format!("unknown opcode: {}", opcode)),
-        }
-    }
-
-    fn read_param(&mut self) -> Param {
-        let word = self.memory[self.ip];
-        self.ip += 1;
-
-        if word < MOD {
-            Param::Literal(word)
-        } else {
-            // TODO: ensure that we aren't trying to target an invalid register
-            let register = word - MOD;
-            Param::Register(register as usize)
+                    self.set(ip, output, value)?;
+                } else {
+                    self.ip = ip;
+                    self.state = State::WaitingForInput;
+                }
+            }
+            Operation::NoOp => {}
         }
+
+        Ok(())
+    }
+
+    pub fn get_next_operation(&mut self) -> Result<Operation, VmError> {
+        match decode(&self.memory, self.ip) {
+            Ok((operation, length)) => {
+                self.ip += length;
+                Ok(operation)
+            }
+            Err(error) => {
+                self.ip += 1;
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Decodes the instruction at the IP without executing it, returning it
+    /// along with the address of the instruction that follows.
+    pub fn peek_operation(&self) -> Result<(Operation, usize), VmError> {
+        let (operation, length) = decode(&self.memory, self.ip)?;
+        Ok((operation, self.ip + length))
     }
 
     fn get(&self, param: Param) -> Word {
@@ -287,36 +822,114 @@ impl VM {
         }
     }
 
-    fn get_memory(&self, location: Param) -> Word {
-        self.memory[self.get(location) as usize]
+    fn load(&self, ip: usize, location: Param) -> Result<Word, VmError> {
+        let address = self.get(location);
+
+        self.memory
+            .get(address as usize)
+            .copied()
+            .ok_or(VmError::AddressOutOfBounds { ip, address })
     }
 
-    fn set(&mut self, param: Param, value: Word) {
+    fn set(&mut self, ip: usize, param: Param, value: Word) -> Result<(), VmError> {
         match param {
-            Param::Register(index) => self.registers[index] = value,
-            Param::Literal(_) => self.error("attempted to write to a literal".to_owned()),
+            Param::Register(index) => {
+                self.record(Change::Register(index, self.registers[index]));
+                self.registers[index] = value;
+                Ok(())
+            }
+            Param::Literal(literal) => Err(VmError::WriteToLiteral { ip, literal }),
         }
     }
 
-    fn set_memory(&mut self, location: Param, value: Word) {
-        let index = self.get(location) as usize;
+    fn store(&mut self, ip: usize, location: Param, value: Word) -> Result<(), VmError> {
+        let address = self.get(location);
 
-        self.memory[index] = value
+        match self.memory.get(address as usize).copied() {
+            Some(old) => {
+                self.record(Change::Memory(address as usize, old));
+                self.memory[address as usize] = value;
+                if let Some(memo) = self.memo.as_mut() {
+                    memo.memory_written(address as usize);
+                }
+                Ok(())
+            }
+            None => Err(VmError::AddressOutOfBounds { ip, address }),
+        }
     }
 
     fn push(&mut self, value: Word) {
+        self.record(Change::Pushed);
         self.stack.push(value)
     }
 
     fn pop(&mut self) -> Option<Word> {
-        self.stack.pop()
+        let value = self.stack.pop()?;
+        self.record(Change::Popped(value));
+        Some(value)
+    }
+
+    fn call_hook(&mut self, address: usize) -> HookResult {
+        // The hook is taken out while it runs so it can borrow the VM.
+        let mut hook = match self.hooks.0.remove(&address) {
+            Some(hook) => hook,
+            None => return HookResult::Declined,
+        };
+        let result = hook(&mut HookContext { vm: self });
+        self.hooks.0.entry(address).or_insert(hook);
+
+        let writes = std::mem::take(&mut self.hook_writes);
+        let returned = result == HookResult::Returned;
+        if returned || !writes.is_empty() {
+            self.record_event(Event::Hook {
+                address,
+                returned,
+                writes,
+            });
+        }
+
+        // What a hook did can't be seen, so calls around it aren't cached.
+        if result == HookResult::Returned {
+            if let Some(memo) = self.memo.as_mut() {
+                memo.taint();
+            }
+        }
+        result
+    }
+
+    /// Writes a cached result for the call instead of making it.
+    fn call_memoized(&mut self, ip: usize, target: usize) -> Result<bool, VmError> {
+        let registers = &self.registers;
+        let writes = match self
+            .memo
+            .as_mut()
+            .and_then(|memo| memo.lookup(target, registers))
+        {
+            Some(writes) => writes,
+            None => return Ok(false),
+        };
+
+        for (index, value) in writes {
+            self.set(ip, Param::Register(index), value)?;
+        }
+        Ok(true)
+    }
+
+    fn record(&mut self, change: Change) {
+        if let Some(log) = self.undo.as_mut() {
+            log.record(change);
+        }
+
+        if self.tracing {
+            self.traced_changes.push(change);
+        }
     }
 
     fn jump(&mut self, to: Param) {
         self.ip = self.get(to) as usize
     }
 
-    fn error(&mut self, error: String) {
+    fn error(&mut self, error: VmError) {
         self.state = State::Errored(error);
     }
 
@@ -325,6 +938,125 @@ impl VM {
     }
 }
 
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::InvalidRegister { ip, word } => {
+                write!(f, "invalid register {} at {}", word, ip)
+            }
+            VmError::InvalidOpcode { ip, opcode } => {
+                write!(f, "unknown opcode: {} at {}", opcode, ip)
+            }
+            VmError::IpOutOfBounds { ip, address } => write!(
+                f,
+                "instruction at {} reads past the end of memory at {}",
+                ip, address
+            ),
+            VmError::AddressOutOfBounds { ip, address } => {
+                write!(f, "memory address {} out of bounds at {}", address, ip)
+            }
+            VmError::DivisionByZero { ip, dividend } => {
+                write!(f, "attempted {} mod 0 at {}", dividend, ip)
+            }
+            VmError::WriteToLiteral { ip, literal } => {
+                write!(f, "attempted to write to literal {} at {}", literal, ip)
+            }
+            VmError::EmptyStack { ip } => write!(f, "attempted to pop an empty stack at {}", ip),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<DecodeError> for VmError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::InvalidOpcode { address, opcode } => VmError::InvalidOpcode {
+                ip: address,
+                opcode,
+            },
+            DecodeError::InvalidRegister { address, word } => {
+                VmError::InvalidRegister { ip: address, word }
+            }
+            DecodeError::Truncated { address, end } => VmError::IpOutOfBounds {
+                ip: address,
+                address: end,
+            },
+        }
+    }
+}
+
+impl HookContext<'_> {
+    pub fn get_registers(&self) -> &[Word] {
+        &self.vm.registers
+    }
+
+    /// Refuses what `VM::set_register` refuses.
+    pub fn set_register(&mut self, index: usize, value: Word) -> bool {
+        if index >= REGISTERS || value >= REGISTER_LIMIT {
+            return false;
+        }
+        self.vm
+            .record(Change::Register(index, self.vm.registers[index]));
+        self.vm.registers[index] = value;
+        self.vm
+            .hook_writes
+            .push(HookWrite::Register { index, value });
+        true
+    }
+
+    pub fn get_memory(&self) -> &[Word] {
+        &self.vm.memory
+    }
+
+    /// Refuses what `VM::set_memory` refuses.
+    pub fn set_memory(&mut self, address: usize, value: Word) -> bool {
+        match self.vm.memory.get(address).copied() {
+            Some(_) if value >= WORD_LIMIT => false,
+            Some(old) => {
+                self.vm.record(Change::Memory(address, old));
+                self.vm.memory[address] = value;
+                if let Some(memo) = self.vm.memo.as_mut() {
+                    memo.memory_written(address);
+                }
+                self.vm
+                    .hook_writes
+                    .push(HookWrite::Memory { address, value });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_stack(&self) -> &[Word] {
+        &self.vm.stack
+    }
+
+    pub fn push(&mut self, value: Word) {
+        self.vm.push(value);
+        self.vm.hook_writes.push(HookWrite::Push(value));
+    }
+
+    pub fn pop(&mut self) -> Option<Word> {
+        let value = self.vm.pop()?;
+        if let Some(memo) = self.vm.memo.as_mut() {
+            memo.stack_shrunk(self.vm.stack.len());
+        }
+        self.vm.hook_writes.push(HookWrite::Pop);
+        Some(value)
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.vm.cycles
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -347,6 +1079,108 @@ mod tests {
         assert_eq!(vm.ip, 10);
     }
 
+    #[test]
+    fn step_test() {
+        let mut vm = VM::new(vec![21, 21, 21, 0]);
+        assert_eq!(vm.step(), State::Paused);
+        assert_eq!(vm.ip, 1);
+
+        assert_eq!(vm.run_for(2), State::Paused);
+        assert_eq!(vm.ip, 3);
+
+        assert_eq!(vm.step(), State::Halted);
+        assert_eq!(vm.step(), State::Halted);
+        assert_eq!(vm.cycles, 4);
+    }
+
+    #[test]
+    fn run_until_test() {
+        let mut vm = VM::new(vec![9, 32768, 32768, 1, 6, 0]);
+        assert_eq!(vm.run_until(|vm| vm.registers[0] == 5), State::Paused);
+        assert_eq!(vm.registers[0], 5);
+        assert_eq!(vm.ip, 4);
+
+        assert_eq!(vm.run_until(|vm| vm.registers[0] == 7), State::Paused);
+        assert_eq!(vm.registers[0], 7);
+    }
+
+    #[test]
+    fn cycle_budget_test() {
+        let mut vm = VM::new(vec![6, 0]);
+        vm.set_cycle_budget(Some(100));
+
+        assert_eq!(vm.run(), State::Paused);
+        assert_eq!(vm.cycles, 100);
+        assert_eq!(vm.run(), State::Paused);
+        assert_eq!(vm.cycles, 200);
+    }
+
+    #[test]
+    fn step_back_test() {
+        let program = vec![
+            2, 5, 3, 32769, 16, 14, 32769, 20, 32768, 19, 32768, 0, 0, 0, 0,
+        ];
+        let mut vm = VM::new(program.clone());
+        vm.enable_undo(100);
+        vm.add_input(b'a' as Word);
+
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.memory[14], 5);
+        assert_eq!(vm.output.pending(), vec![b'a' as Word]);
+        assert_eq!(vm.cycles, 6);
+
+        while vm.step_back() {}
+
+        assert_eq!(vm.ip, 0);
+        assert_eq!(vm.cycles, 0);
+        assert_eq!(vm.memory, program);
+        assert_eq!(vm.registers, vec![0; REGISTERS]);
+        assert!(vm.stack.is_empty());
+        assert!(vm.output.pending().is_empty());
+        assert_eq!(vm.input.pending(), vec![b'a' as Word]);
+
+        // Output a frontend has already taken stays taken.
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.get_output(), "a");
+        while vm.step_back() {}
+
+        assert_eq!(vm.cycles, 0);
+        assert_eq!(vm.memory, program);
+        assert!(vm.output.pending().is_empty());
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.get_output(), "a");
+    }
+
+    #[test]
+    fn run_back_test() {
+        let mut program = vec![9, 32768, 32768, 1, 16, 20, 32768, 6, 0];
+        program.resize(21, 0);
+        let mut vm = VM::new(program);
+        vm.enable_undo(1_000);
+        vm.run_for(30);
+
+        assert!(vm.run_back_to_write(20));
+        assert_eq!(vm.ip, 4);
+        assert_eq!(vm.registers[0], 10);
+        assert_eq!(vm.memory[20], 9);
+
+        assert!(vm.run_back_to(0));
+        assert_eq!(vm.registers[0], 9);
+        assert!(!vm.run_back_to(42));
+        assert_eq!(vm.cycles, 0);
+    }
+
+    #[test]
+    fn undo_limit_test() {
+        let mut vm = VM::new(vec![21, 6, 0]);
+        vm.enable_undo(10);
+        vm.run_for(25);
+
+        assert_eq!(vm.get_undo_log().unwrap().len(), 10);
+        assert!(!vm.run_back_to(42));
+        assert_eq!(vm.cycles, 15);
+    }
+
     #[test]
     fn not_test() {
         let mut vm = VM::new(vec![14, 32768, 32767, 16, 6, 32768, 42]);
@@ -357,6 +1191,65 @@ mod tests {
         assert_eq!(vm.memory[6], 0);
     }
 
+    #[test]
+    fn invalid_register_test() {
+        let mut vm = VM::new(vec![1, 32776, 1, 0]);
+        vm.run();
+
+        assert_eq!(
+            vm.state,
+            State::Errored(VmError::InvalidRegister { ip: 0, word: 32776 })
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_test() {
+        let mut vm = VM::new(vec![21, 9, 32768]);
+        vm.run();
+        assert_eq!(
+            vm.state,
+            State::Errored(VmError::IpOutOfBounds { ip: 1, address: 3 })
+        );
+
+        let mut vm = VM::new(vec![16, 100, 1, 0]);
+        vm.run();
+        assert_eq!(
+            vm.state,
+            State::Errored(VmError::AddressOutOfBounds {
+                ip: 0,
+                address: 100
+            })
+        );
+    }
+
+    #[test]
+    fn fault_test() {
+        let mut vm = VM::new(vec![11, 32768, 5, 0, 0]);
+        vm.run();
+        assert_eq!(
+            vm.state,
+            State::Errored(VmError::DivisionByZero { ip: 0, dividend: 5 })
+        );
+
+        let mut vm = VM::new(vec![21, 3, 32768, 0]);
+        vm.run();
+        assert_eq!(vm.state, State::Errored(VmError::EmptyStack { ip: 1 }));
+
+        let mut vm = VM::new(vec![1, 7, 1, 0]);
+        vm.run();
+        assert_eq!(
+            vm.state,
+            State::Errored(VmError::WriteToLiteral { ip: 0, literal: 7 })
+        );
+
+        let mut vm = VM::new(vec![22]);
+        vm.run();
+        assert_eq!(
+            vm.state,
+            State::Errored(VmError::InvalidOpcode { ip: 0, opcode: 22 })
+        );
+    }
+
     #[test]
     fn add_and_output_test() {
         let mut vm = VM::new(vec![9, 32768, 32769, 88, 19, 32768, 0]);
@@ -365,4 +1258,92 @@ mod tests {
         assert_eq!(vm.state, State::Halted);
         assert_eq!(vm.get_output(), "X");
     }
+
+    #[test]
+    fn raw_output_test() {
+        let program = vec![19, 200, 19, 1000, 19, 65, 0];
+        let mut vm = VM::new(program.clone());
+        vm.run();
+        assert_eq!(vm.get_output(), "\u{fffd}\u{fffd}A");
+
+        let mut vm = VM::new(program.clone());
+        vm.run();
+        assert_eq!(vm.snapshot().output, vec![200, 1000, 65]);
+        assert_eq!(vm.get_output_words(), vec![200, 1000, 65]);
+
+        let mut vm = VM::new(program);
+        vm.run();
+        assert_eq!(vm.get_output_bytes().unwrap_err().value, 1000);
+    }
+
+    #[test]
+    fn streams_test() {
+        use crate::streams::{ReaderSource, SharedBuffer, WriterSink};
+
+        // Upper-cases one line of input: in, stop at newline, subtract 32, out.
+        let program = vec![
+            20, 32768, 4, 32769, 32768, 10, 7, 32769, 17, 9, 32768, 32768, 32736, 19, 32768, 6, 0,
+            0,
+        ];
+        let buffer = SharedBuffer::default();
+        let mut vm = VM::new(program);
+        vm.add_input(b'o' as Word);
+        vm.set_input(ReaderSource::new(io::Cursor::new("k\n")));
+        vm.set_output(WriterSink::new(buffer.clone()));
+
+        assert_eq!(vm.run(), State::Halted);
+        vm.flush_output().unwrap();
+        assert_eq!(*buffer.0.borrow(), b"OK");
+        assert_eq!(vm.get_output(), "");
+    }
+
+    #[test]
+    fn setter_test() {
+        let mut vm = VM::new(vec![0, 0]);
+
+        assert!(vm.set_register(7, REGISTER_LIMIT - 1));
+        assert!(!vm.set_register(7, REGISTER_LIMIT));
+        assert!(!vm.set_register(REGISTERS, 0));
+        assert!(vm.set_memory(1, WORD_LIMIT - 1));
+        assert!(!vm.set_memory(1, WORD_LIMIT));
+        assert!(!vm.set_memory(2, 0));
+        assert_eq!(vm.get_registers()[7], REGISTER_LIMIT - 1);
+        assert_eq!(vm.get_memory(), &[0, WORD_LIMIT - 1]);
+    }
+
+    #[test]
+    fn hook_test() {
+        // setr(#0, 21); call(8); out(#0); halt; at 8: add(#0, #0, 1); ret
+        let program = vec![1, 32768, 21, 17, 8, 19, 32768, 0, 9, 32768, 32768, 1, 18];
+
+        let program_length = program.len();
+        let mut vm = VM::new(program.clone());
+        vm.set_hook(8, move |hooked: &mut HookContext| {
+            let value = hooked.get_registers()[0];
+            assert!(!hooked.set_register(0, REGISTER_LIMIT));
+            assert!(!hooked.set_register(REGISTERS, value));
+            assert!(!hooked.set_memory(0, WORD_LIMIT));
+            assert!(!hooked.set_memory(program_length, 0));
+            assert!(hooked.set_memory(program_length - 1, MOD));
+            assert!(hooked.set_memory(program_length - 1, 18));
+            hooked.set_register(0, value * 2);
+            HookResult::Returned
+        });
+        vm.enable_undo(10);
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.get_output_words(), vec![42]);
+        assert_eq!(vm.cycles, 4);
+        assert_eq!(vm.get_hooked_addresses(), vec![8]);
+
+        vm.run_back_to(5);
+        assert_eq!(vm.registers[0], 42);
+        vm.step_back();
+        assert_eq!(vm.registers[0], 21);
+
+        let mut vm = VM::new(program);
+        vm.set_hook(8, |_: &mut HookContext| HookResult::Declined);
+        assert_eq!(vm.run(), State::Halted);
+        assert_eq!(vm.get_output_words(), vec![22]);
+        assert!(vm.remove_hook(8));
+    }
 }